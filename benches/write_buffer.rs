@@ -0,0 +1,35 @@
+//! Compares building a large generated fixture with and without a write buffer. Run with
+//! `cargo bench --bench write_buffer`.
+
+use ephemeral::{
+    builder::{Builder, GenericBuilder},
+    Dir, DEFAULT_WRITE_BUFFER_SIZE,
+};
+use std::time::{Duration, Instant};
+
+const LEN: usize = 64 * 1024 * 1024;
+const RUNS: u32 = 5;
+
+fn time_build(buffer_size: usize) -> Duration {
+    let mut total = Duration::default();
+    for _ in 0..RUNS {
+        let builder = GenericBuilder::new("tmp_bench_write_buffer")
+            .add_dir(
+                Dir::new("tmp_bench_write_buffer").add_generated_file("large", LEN, |i| i as u8),
+            )
+            .write_buffer_size(buffer_size);
+
+        let start = Instant::now();
+        let project = builder.build().expect("cannot create project");
+        total += start.elapsed();
+        project.clear();
+    }
+
+    total / RUNS
+}
+
+fn main() {
+    for &size in [0, 8 * 1024, DEFAULT_WRITE_BUFFER_SIZE, 1024 * 1024].iter() {
+        println!("buffer {:>8} bytes: {:?} per build", size, time_build(size));
+    }
+}
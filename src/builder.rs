@@ -1,15 +1,39 @@
 use crate::{
-    rust_tools::{Edition, Manifest},
-    Dir, File, FilePath, Project,
+    error::EphemeralError,
+    rust_tools::{
+        self, BenchTarget, Dependency, DependencyStyle, Edition, LintLevel, Manifest,
+        PublishSetting, Resolver, TestTarget, WorkspaceManifest,
+    },
+    spec::ProjectSpec,
+    Dir, DuplicateDirs, File, Project,
+};
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsStr,
+    fmt::Debug,
+    fs, io,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
 };
-use std::{error::Error, fmt::Debug, fs::File as FsFile, io::Write, path::PathBuf};
 
-#[cfg(feature = "nightly")]
+#[cfg(feature = "tar")]
+use crate::tar::TarWriter;
+#[cfg(feature = "zip")]
+use crate::zip::ZipWriter;
 use std::convert::TryInto;
+#[cfg(any(feature = "tar", feature = "zip"))]
+use std::io::Write;
 
 #[derive(Clone, Debug)]
 pub struct GenericBuilder {
-    path: PathBuf,
     project: Project,
 }
 
@@ -19,46 +43,359 @@ impl GenericBuilder {
         T: Into<PathBuf> + Clone,
     {
         GenericBuilder {
-            path: path.clone().into(),
             project: Project::new(path),
         }
     }
+
+    /// Creates a builder with room for `dirs` dirs besides the root one, so adding many dirs
+    /// doesn't reallocate.
+    pub fn with_capacity<T: Into<PathBuf>>(path: T, dirs: usize) -> GenericBuilder {
+        GenericBuilder {
+            project: Project::with_capacity(path, dirs),
+        }
+    }
+
+    /// Creates a builder holding the dirs, files and manifest described by `spec`.
+    pub fn from_spec(spec: ProjectSpec) -> Result<GenericBuilder, EphemeralError> {
+        let mut builder = GenericBuilder::new(&spec.path);
+        if let Some(ref manifest) = spec.manifest {
+            let contents = rust_tools::to_toml(manifest)?;
+            builder =
+                builder.add_dir(Dir::new(&spec.path).add_file("Cargo.toml", contents.as_bytes()));
+        }
+        for dir in spec.dirs.iter() {
+            let mut new_dir = Dir::with_capacity(spec.path.join(&dir.path), dir.files.len());
+            for file in dir.files.iter() {
+                new_dir = new_dir.add_file(&file.path, &file.bytes()?);
+            }
+            builder = builder.add_dir(new_dir);
+        }
+
+        Ok(builder)
+    }
+
+    pub(crate) fn from_project(mut project: Project) -> GenericBuilder {
+        project.unguard();
+        GenericBuilder { project }
+    }
+
+    /// Turns the builder into a `RustBuilder` holding the same dirs and files, with a default
+    /// manifest and no `Cargo.toml` until `add_cargo_toml()` is called.
+    pub fn into_rust(self) -> RustBuilder {
+        RustBuilder::from_project(self.project)
+    }
 }
 
 pub trait Builder: Clone + Debug + Sized {
-    fn add_dir(self, dir: Dir) -> Self {
-        self.project().dirs.push(dir.to_owned());
+    fn add_dir(mut self, dir: Dir) -> Self {
+        self.project_mut().dirs.push(dir);
 
-        println!("{:?}", &self.project().dirs);
         self
     }
 
-    fn build(self) -> Result<Project, Box<Error>> {
-        println!("{:?}", self.project().dirs);
-        for dir in self.project().dirs.iter() {
-            dir.path.mkdir_p()?;
-            for file in dir.files.iter() {
-                FsFile::create(&file.path)?.write_all(&file.contents)?;
-            }
-        }
+    fn build(mut self) -> Result<Project, EphemeralError> {
+        self.project_mut().create_dirs()?;
+
+        Ok(self.into_project().guard_if_clearing())
+    }
+
+    /// Passes the contents of every file through `f` right before it is written by `build()`. `f`
+    /// gets the file's path, so it can skip files it doesn't care about. Calling this again chains
+    /// the new transform after the previous one.
+    fn map_contents<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Path, &[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        self.project_mut().map_contents(f);
+        self
+    }
+
+    /// Sets whether the built project deletes itself when dropped, so a test doesn't have to call
+    /// `clear()`. Defaults to `false`, leaving the project on disk for inspection. Only the
+    /// project `build()` returns deletes itself; dropping the builder, a clone of it or of the
+    /// project, or a workspace member built along with its workspace never does.
+    fn clear_on_drop(mut self, yes: bool) -> Self {
+        self.project_mut().set_clear_on_drop(yes);
+        self
+    }
+
+    /// Sorts the dirs added so far, and each dir's files and child dirs, by path, so that
+    /// `build()` creates them in the same order no matter how they were added. Parents are always
+    /// created before their children. See `Project::plan()` for the resulting order.
+    fn sort_entries(mut self) -> Self {
+        self.project_mut().sort_entries();
+        self
+    }
+
+    /// Sets whether `build()` reads each file back after writing it and checks that it holds what
+    /// was written, failing with `EphemeralError::WriteVerificationFailed` if the length differs
+    /// or `EphemeralError::WriteCorrupted` if the contents do. The contents are compared by
+    /// checksum. Off by default since it reads every file again.
+    fn verify_writes(mut self, yes: bool) -> Self {
+        self.project_mut().set_verify_writes(yes);
+        self
+    }
+
+    /// Sets what `build()` does with dirs added more than once at the same path, see
+    /// `DuplicateDirs`. Defaults to `DuplicateDirs::Append`.
+    fn duplicate_dirs(mut self, policy: DuplicateDirs) -> Self {
+        self.project_mut().set_duplicate_dirs(policy);
+        self
+    }
+
+    /// Sets whether `build()` skips files whose contents on disk already match, so rebuilding a
+    /// fixture after a small change leaves the other files and their mtimes alone. Off by default.
+    /// Files that are no longer part of the project are not removed.
+    fn incremental(mut self, yes: bool) -> Self {
+        self.project_mut().set_incremental(yes);
+        self
+    }
+
+    /// Moves the dir or file added at `from` to `to` before anything is written, e.g. to adjust a
+    /// tree built by a shared helper. Files and child dirs of a moved dir move along with it.
+    /// Paths include the project root, like the ones given to `Dir::new()`. Only whole path
+    /// components match, so `a/b` doesn't move `a/bc`. Does nothing if no entry matches.
+    fn rename_path<P, Q>(mut self, from: P, to: Q) -> Self
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        self.project_mut().rename_path(from.as_ref(), to.as_ref());
+        self
+    }
+
+    /// Makes `build()` create the dir or file at `after` only once the one at `before` exists, e.g.
+    /// `Cargo.toml` before `src/` for tools that react to creation events. Paths include the
+    /// project root like in `rename_path()`. Everything else keeps its usual order, see
+    /// `Project::plan()`. Entries always come after the dir they are in, so contradicting that or
+    /// other constraints fails the build with `EphemeralError::CreateOrderCycle`, and naming a
+    /// path that isn't part of the project fails it with `EphemeralError::NotFound`.
+    fn create_after<P, Q>(mut self, after: P, before: Q) -> Self
+    where
+        P: Into<PathBuf>,
+        Q: Into<PathBuf>,
+    {
+        self.project_mut().create_after(after.into(), before.into());
+        self
+    }
+
+    /// Counts the dirs added so far, nested ones and the root included. Dirs added more than once
+    /// at the same path count once.
+    fn dir_count(&self) -> usize {
+        self.project().dir_count()
+    }
+
+    /// Counts the files added so far, in every dir. Files a builder only generates at build time,
+    /// like `RustBuilder`'s `Cargo.toml`, aren't counted.
+    fn file_count(&self) -> usize {
+        self.project().file_count()
+    }
+
+    /// Whether nothing but the root dir has been added so far.
+    fn is_empty(&self) -> bool {
+        self.file_count() == 0 && self.dir_count() <= 1
+    }
+
+    /// Calls `f` with the project as it is so far and returns the builder unchanged, like
+    /// `Iterator::inspect`, e.g. to log or assert in the middle of a chain.
+    fn inspect<F: FnOnce(&Project)>(self, f: F) -> Self {
+        f(self.project());
+        self
+    }
+
+    /// Sets flags like `--offline` or `--locked` that the built project passes to cargo before
+    /// the arguments of every invocation, see `Project::set_cargo_flags()`.
+    fn cargo_flags(mut self, flags: &[&str]) -> Self {
+        self.project_mut().set_cargo_flags(flags);
+        self
+    }
 
-        Ok(self.project())
+    /// Sets whether `build()` first checks with `Project::validate()` that every path is under the
+    /// project root, failing before anything is written otherwise. Off by default.
+    fn strict_paths(mut self, yes: bool) -> Self {
+        self.project_mut().set_strict_paths(yes);
+        self
+    }
+
+    /// Sets the Unix permission bits, e.g. `0o644`, of every file `build()` writes, so they don't
+    /// depend on the umask. Files added with `Dir::add_file_with_mode()` keep their own mode, and
+    /// read-only files still lose their write bits. Ignored on other platforms.
+    fn default_mode(mut self, mode: u32) -> Self {
+        self.project_mut().set_default_mode(mode);
+        self
+    }
+
+    /// Sets the capacity of the buffer each file is written through, `DEFAULT_WRITE_BUFFER_SIZE`
+    /// by default. `0` writes straight to the file.
+    fn write_buffer_size(mut self, size: usize) -> Self {
+        self.project_mut().set_write_buffer_size(size);
+        self
+    }
+
+    /// Writes the project as a tar archive to `writer` instead of creating it on disk. Entries
+    /// are relative to the project root and include every dir, with mode `755` (`555` if
+    /// read-only), and every file, with its own mode, else `default_mode()`, else `644`, minus
+    /// the write bits if read-only. Owners and timestamps are zero, so the same project always
+    /// gives the same archive. Fails with `EphemeralError::OutsideRoot` on paths that aren't under
+    /// the root, and with an I/O error on paths ustar can't hold or files of 8 GiB or more, in
+    /// both cases before anything is written.
+    #[cfg(feature = "tar")]
+    fn build_tar<W: Write>(self, writer: W) -> Result<(), EphemeralError> {
+        let project = self.project();
+        let mut tar = TarWriter::new(&project.path, writer);
+        tar.add_project(project)?;
+
+        tar.finish()
+    }
+
+    /// Like `build_tar()`, but writes a zip archive with the same entries and modes, dated
+    /// 1980-01-01. Entries are stored uncompressed and without zip64, so `writer` only needs to be
+    /// `Write`, not `Seek`. Archives over 4 GiB or with more than 65535 entries, and paths longer
+    /// than 65535 bytes, fail with an I/O error. The archive is assembled in memory and only
+    /// written once it fits, so nothing is written to `writer` on failure.
+    #[cfg(feature = "zip")]
+    fn build_zip<W: Write>(self, writer: W) -> Result<(), EphemeralError> {
+        let project = self.project();
+        let mut zip = ZipWriter::new(&project.path, writer);
+        zip.add_project(project)?;
+
+        zip.finish()
     }
 
-    fn project(&self) -> Project;
+    fn project(&self) -> &Project;
+
+    fn project_mut(&mut self) -> &mut Project;
+
+    fn into_project(self) -> Project;
 }
 
 impl Builder for GenericBuilder {
-    fn project(&self) -> Project {
-        self.project.clone()
+    fn project(&self) -> &Project {
+        &self.project
+    }
+
+    fn project_mut(&mut self) -> &mut Project {
+        &mut self.project
+    }
+
+    fn into_project(self) -> Project {
+        self.project
     }
 }
 
+/// Builds every builder concurrently and returns the results in the same order. At most
+/// `available_parallelism()` builds run at a time. Each builder should use a distinct root so the
+/// builds don't write to the same paths.
+pub fn build_many<B>(builders: Vec<B>) -> Vec<Result<Project, EphemeralError>>
+where
+    B: Builder + Send,
+{
+    on_workers(builders, parallelism(), Builder::build)
+}
+
+fn parallelism() -> usize {
+    thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+/// Runs `f` on every item on up to `workers` threads, each taking the next item from a shared
+/// queue until it is empty, and returns the results in the order of `items`.
+fn on_workers<T, R, F>(items: Vec<T>, workers: usize, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let workers = workers.min(items.len());
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let mut results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = vec![];
+                    loop {
+                        let next = queue.lock().expect("build queue poisoned").next();
+                        match next {
+                            Some((i, item)) => results.push((i, f(item))),
+                            None => return results,
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("build thread panicked"))
+            .collect()
+    });
+    results.sort_by_key(|&(i, _)| i);
+
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Suffix of the next dir created by `build_each()` or `RustBuilder::scratch()`, shared so
+/// repeated calls don't reuse dirs.
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns a path under `std::env::temp_dir()` named `<name>-<pid>-<n>`, with `n` from
+/// `NEXT_DIR`, so neither repeated calls nor other processes pick the same one.
+fn fresh_temp_dir(name: &str) -> PathBuf {
+    env::temp_dir().join(format!(
+        "{}-{}-{}",
+        name,
+        process::id(),
+        NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Dir of the stub crates added by `RustBuilder::scratch_registry()`, relative to the project
+/// root. Also the name of the source replacing crates.io.
+const SCRATCH_REGISTRY: &str = ".scratch-registry";
+
+/// Builds one project per case from the builder `f` returns for it, concurrently like
+/// `build_many()`. Each project is moved to a fresh dir under `std::env::temp_dir()` named after
+/// its builder's root, e.g. `tmp-<pid>-3` for `RustBuilder::new("tmp")`, so `f` can use the same
+/// root for every case. A case whose dir already exists, say left behind by an earlier run, fails
+/// with an `AlreadyExists` error instead of being built into it.
+pub fn build_each<C, I, F>(cases: I, f: F) -> Vec<Result<Project, EphemeralError>>
+where
+    I: IntoIterator<Item = C>,
+    F: Fn(&C) -> RustBuilder,
+{
+    let builders = cases
+        .into_iter()
+        .map(|case| {
+            let builder = f(&case);
+            let name = builder
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy();
+            let root = fresh_temp_dir(&name);
+            builder.move_root(root)
+        })
+        .collect();
+
+    on_workers(builders, parallelism(), |builder| {
+        fs::create_dir(&builder.path).map_err(|err| {
+            let message = format!("can't create {}: {}", builder.path.display(), err);
+            io::Error::new(err.kind(), message)
+        })?;
+        builder.build()
+    })
+}
+
 #[derive(Clone, Debug)]
 pub struct RustBuilder {
     path: PathBuf,
     project: Project,
     manifest: Manifest,
+    cargo_toml: bool,
+    modules: BTreeMap<String, String>,
+    raw_tables: Vec<String>,
+    offline_lock: bool,
+    dependency_style: DependencyStyle,
+    scratch_registry: bool,
 }
 
 impl RustBuilder {
@@ -70,104 +407,2205 @@ impl RustBuilder {
             project: Project::new(path.clone()),
             path: path.into(),
             manifest: Manifest::default(),
+            cargo_toml: false,
+            modules: BTreeMap::new(),
+            raw_tables: vec![],
+            offline_lock: false,
+            dependency_style: DependencyStyle::default(),
+            scratch_registry: false,
         }
     }
 
-    #[cfg(feature = "nightly")]
-    pub fn add_cargo_toml(mut self, manifest: Manifest) -> Result<Self, Box<Error>> {
+    /// Creates a builder for a throwaway library crate named `scratch` in a fresh dir under
+    /// `std::env::temp_dir()`, with a `Cargo.toml` and an empty `src/lib.rs`. The project is
+    /// deleted when dropped, as with `clear_on_drop(true)`, unless that is turned off again.
+    pub fn scratch() -> RustBuilder {
+        let path = fresh_temp_dir("ephemeral");
+        let manifest = Manifest::try_from("scratch", "0.1.0", &[], None, None)
+            .expect("the scratch manifest is valid");
+
+        RustBuilder::new(path.clone())
+            .add_cargo_toml(manifest)
+            .expect("the scratch manifest serializes")
+            .add_dir(Dir::new(path.join("src")).add_file("lib.rs", b""))
+            .clear_on_drop(true)
+    }
+
+    /// Creates a builder for a project in the `name` dir inside `parent`, i.e. at
+    /// `<parent>/<name>/`, so the crate dir can be named independently of where it is created.
+    pub fn with_dir_name<T>(parent: T, name: &str) -> RustBuilder
+    where
+        T: Into<PathBuf>,
+    {
+        RustBuilder::new(parent.into().join(name))
+    }
+
+    fn move_root(mut self, path: PathBuf) -> Self {
+        self.project.move_root(path.clone());
+        self.path = path;
+
+        self
+    }
+
+    /// Builds the project and runs `cargo check` in it, returning `EphemeralError::CargoFailed`
+    /// with cargo's diagnostics if the check fails, in which case the project is removed again.
+    ///
+    /// This needs cargo installed, and registry or network access if the manifest has
+    /// dependencies that aren't available locally.
+    pub fn build_and_check(self) -> Result<Project, EphemeralError> {
+        let project = self.build()?;
+        let output = project.cargo(["check"])?;
+        if !output.status.success() {
+            project.clear();
+            return Err(EphemeralError::CargoFailed {
+                status: output.status,
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            });
+        }
+
+        Ok(project)
+    }
+
+    pub(crate) fn from_project(mut project: Project) -> RustBuilder {
+        project.unguard();
+        RustBuilder {
+            path: project.path.clone(),
+            project,
+            manifest: Manifest::default(),
+            cargo_toml: false,
+            modules: BTreeMap::new(),
+            raw_tables: vec![],
+            offline_lock: false,
+            dependency_style: DependencyStyle::default(),
+            scratch_registry: false,
+        }
+    }
+
+    /// Sets the manifest and adds a `Cargo.toml` to the root of the project. The file is written
+    /// from the manifest at build time, so later setters like `edition()` are reflected in it.
+    pub fn add_cargo_toml(mut self, manifest: Manifest) -> Result<Self, EphemeralError> {
         self.manifest = manifest;
-        let contents: Vec<u8> = self.clone().manifest.try_into()?;
-        self.project.dirs[0]
-            .files
-            .push(File::new(self.path.join("Cargo.toml"), &contents));
+        self.manifest_bytes()?;
+        self.cargo_toml = true;
         Ok(self)
     }
 
-    #[cfg(not(feature = "nightly"))]
-    pub fn add_cargo_toml(mut self, manifest: Manifest) -> Result<Self, Box<Error>> {
-        self.manifest = manifest;
-        let contents: Vec<u8> = Ok(toml::to_string(&self.manifest)?.into_bytes())
-            .map_err(|e: toml::ser::Error| Box::new(e))?;
-        self.project.dirs[0]
-            .files
-            .push(File::new(self.path.join("Cargo.toml"), &contents));
+    /// Writes `contents` as the `Cargo.toml` as is, e.g. a deliberately broken manifest to test
+    /// error handling. This replaces a manifest added with `add_cargo_toml()`, but the typed
+    /// manifest is kept for helpers like `generate_lock_offline()`.
+    pub fn raw_manifest(mut self, contents: &str) -> Self {
+        self.cargo_toml = false;
+
+        self.root_file("Cargo.toml", contents)
+    }
+
+    /// Serializes the current manifest to the TOML that `add_cargo_toml` writes as `Cargo.toml`.
+    ///
+    /// The output is stable so it can be compared byte for byte: `[package]` comes first with its
+    /// keys in a fixed order, followed by `[dependencies]` sorted by crate name (plain versions
+    /// before dependency tables), and the output always ends with exactly one newline.
+    pub fn manifest_toml(&self) -> Result<String, EphemeralError> {
+        let mut toml = self.manifest.to_toml_with(self.dependency_style)?;
+        for table in self.raw_tables.iter() {
+            toml.push('\n');
+            toml.push_str(table);
+        }
+
+        Ok(toml)
+    }
+
+    /// Sets how dependencies with more than a version are written to the `Cargo.toml`, as
+    /// `[dependencies.foo]` tables (the default) or one-line inline tables.
+    pub fn dependency_style(mut self, style: DependencyStyle) -> Self {
+        self.dependency_style = style;
+
+        self
+    }
+
+    /// Appends a `[header]` table with the raw `toml_fragment` as its body to the manifest, for
+    /// keys that aren't modeled by `Manifest`. Raw tables are written after the modeled ones, in
+    /// the order they were added. The table is checked to be valid TOML on its own, but not
+    /// whether its header clashes with another table.
+    pub fn raw_manifest_table(
+        mut self,
+        header: &str,
+        toml_fragment: &str,
+    ) -> Result<Self, EphemeralError> {
+        let table = format!("[{}]\n{}", header, toml_fragment);
+        toml::Value::from_str(&table)?;
+        self.raw_tables
+            .push(format!("{}\n", table.trim_end_matches('\n')));
+
         Ok(self)
     }
 
+    #[cfg(feature = "nightly")]
+    fn manifest_bytes(&self) -> Result<Vec<u8>, EphemeralError> {
+        Ok(self.manifest.clone().try_into()?)
+    }
+
+    #[cfg(not(feature = "nightly"))]
+    fn manifest_bytes(&self) -> Result<Vec<u8>, EphemeralError> {
+        Ok(self.manifest_toml()?.into_bytes())
+    }
+
     pub fn edition(mut self, edition: Edition) -> Self {
         self.manifest.package.edition = edition;
 
         self
     }
-}
 
-impl Builder for RustBuilder {
-    fn build(self) -> Result<Project, Box<Error>> {
-        for dir in self.project().dirs.iter() {
-            dir.path.mkdir_p()?;
-            for file in dir.files.iter() {
-                FsFile::create(&file.path)?.write_all(&file.contents)?;
+    /// Sets the feature resolver version (`resolver = "2"` under `[package]`). Only the resolver
+    /// versions known to cargo ("1", "2" and "3") are accepted.
+    pub fn resolver(mut self, resolver: &str) -> Result<Self, EphemeralError> {
+        self.manifest.package.resolver = Some(Resolver::from_str(resolver)?);
+
+        Ok(self)
+    }
+
+    /// Sets the `publish` key, e.g. `PublishSetting::Bool(false)` for fixtures that must never be
+    /// published.
+    pub fn publish(mut self, publish: PublishSetting) -> Self {
+        self.manifest.package.publish = Some(publish);
+
+        self
+    }
+
+    /// Sets `autobins`, toggling whether cargo discovers binaries in `src/bin/`.
+    pub fn autobins(mut self, autobins: bool) -> Self {
+        self.manifest.package.autobins = Some(autobins);
+
+        self
+    }
+
+    /// Sets `autoexamples`, toggling whether cargo discovers examples in `examples/`.
+    pub fn autoexamples(mut self, autoexamples: bool) -> Self {
+        self.manifest.package.autoexamples = Some(autoexamples);
+
+        self
+    }
+
+    /// Sets `autotests`, toggling whether cargo discovers integration tests in `tests/`.
+    pub fn autotests(mut self, autotests: bool) -> Self {
+        self.manifest.package.autotests = Some(autotests);
+
+        self
+    }
+
+    /// Sets `autobenches`, toggling whether cargo discovers benchmarks in `benches/`.
+    pub fn autobenches(mut self, autobenches: bool) -> Self {
+        self.manifest.package.autobenches = Some(autobenches);
+
+        self
+    }
+
+    /// Adds a file named `name` at the project root, e.g. `.editorconfig`.
+    pub fn root_file<C: AsRef<[u8]>>(mut self, name: &str, contents: C) -> Self {
+        let file = File::new(self.path.join(name), contents.as_ref());
+        self.project.root_mut().files.push(file);
+
+        self
+    }
+
+    /// Adds a `rustfmt.toml` at the project root. Returns an error if `config` isn't valid TOML.
+    pub fn rustfmt_config(self, config: &str) -> Result<Self, EphemeralError> {
+        toml::Value::from_str(config)?;
+
+        Ok(self.root_file("rustfmt.toml", config))
+    }
+
+    /// Adds a `.clippy.toml` at the project root. Returns an error if `config` isn't valid TOML.
+    pub fn clippy_config(self, config: &str) -> Result<Self, EphemeralError> {
+        toml::Value::from_str(config)?;
+
+        Ok(self.root_file(".clippy.toml", config))
+    }
+
+    /// Configures the alternative registry `name` with the index at `index` in the project's
+    /// `.cargo/config.toml`, so dependencies can use it through `Dependency::registry`.
+    pub fn registry(mut self, name: &str, index: &str) -> Result<Self, EphemeralError> {
+        let mut registry = toml::value::Table::new();
+        registry.insert("index".to_owned(), toml::Value::String(index.to_owned()));
+        let mut registries = toml::value::Table::new();
+        registries.insert(name.to_owned(), toml::Value::Table(registry));
+        let mut config = toml::value::Table::new();
+        config.insert("registries".to_owned(), toml::Value::Table(registries));
+        let fragment = rust_tools::to_toml(&config)?;
+
+        self.append_cargo_config(&fragment);
+
+        Ok(self)
+    }
+
+    /// Points cargo at a local directory source of stub crates instead of crates.io, so the
+    /// project builds without network access. At build time, every dependency that has a version
+    /// but no `path` or `registry` gets an empty library crate of that exact version under
+    /// `.scratch-registry/`, and `.cargo/config.toml` replaces `crates-io` with it.
+    ///
+    /// The stubs don't export anything: this is for testing dependency resolution and manifest
+    /// handling, not code that uses the dependencies.
+    pub fn scratch_registry(mut self) -> Self {
+        self.scratch_registry = true;
+
+        self
+    }
+
+    fn add_scratch_registry(&mut self) -> Result<(), EphemeralError> {
+        let mut registry = Dir::new(self.path.join(SCRATCH_REGISTRY));
+        for (name, version) in self.manifest.crates_io_dependencies() {
+            let manifest = Manifest::try_from(
+                name,
+                &version.to_string(),
+                &[],
+                Some(Edition::Edition2015),
+                None,
+            )?;
+            let manifest = rust_tools::to_toml(&manifest)?;
+            registry = registry.dir(format!("{}-{}", name, version), |dir| {
+                dir.add_file("Cargo.toml", manifest.as_bytes())
+                    .add_file(".cargo-checksum.json", br#"{"files":{},"package":null}"#)
+                    .dir("src", |src| src.add_file("lib.rs", b""))
+            });
+        }
+        self.project.dirs.push(registry);
+
+        let mut source = toml::value::Table::new();
+        source.insert(
+            "directory".to_owned(),
+            toml::Value::String(SCRATCH_REGISTRY.to_owned()),
+        );
+        let mut crates_io = toml::value::Table::new();
+        crates_io.insert(
+            "replace-with".to_owned(),
+            toml::Value::String(SCRATCH_REGISTRY.to_owned()),
+        );
+        let mut sources = toml::value::Table::new();
+        sources.insert("crates-io".to_owned(), toml::Value::Table(crates_io));
+        sources.insert(SCRATCH_REGISTRY.to_owned(), toml::Value::Table(source));
+        let mut config = toml::value::Table::new();
+        config.insert("source".to_owned(), toml::Value::Table(sources));
+        let fragment = rust_tools::to_toml(&config)?;
+        self.append_cargo_config(&fragment);
+
+        Ok(())
+    }
+
+    /// Appends `fragment` to the project's `.cargo/config.toml`, creating it if needed.
+    fn append_cargo_config(&mut self, fragment: &str) {
+        let path = self.path.join(".cargo/config.toml");
+        match self.project.file_mut(&path).and_then(File::bytes_mut) {
+            Some(bytes) => bytes.extend_from_slice(fragment.as_bytes()),
+            None => {
+                let dir =
+                    Dir::new(self.path.join(".cargo")).add_file("config.toml", fragment.as_bytes());
+                self.project.dirs.push(dir);
             }
         }
-        Ok(self.project())
     }
 
-    fn project(&self) -> Project {
-        self.project.clone()
+    /// Adds a `Cargo.lock` generated from the manifest at build time, without running cargo. See
+    /// `Manifest::offline_lockfile()` for what it covers.
+    pub fn generate_lock_offline(mut self) -> Self {
+        self.offline_lock = true;
+
+        self
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::rust_tools::{Edition, Manifest};
-    #[test]
-    fn rust_builder_creates_rust_project() {
-        let path = PathBuf::from("foo");
-        let config = Manifest::try_from(
-            "foo",
-            "0.1.0",
-            &["foo <foo@bar.com>"],
-            Some(Edition::Edition2018),
-            None,
-        )
-        .unwrap();
-        let project = RustBuilder::new(&path)
-            .add_cargo_toml(config)
-            .unwrap()
-            .build()
-            .unwrap();
+    /// Adds `name` to `[build-dependencies]`, see `Manifest::add_build_dependency()`.
+    pub fn build_dependency<D>(mut self, name: &str, spec: D) -> Result<Self, EphemeralError>
+    where
+        D: TryInto<Dependency>,
+        EphemeralError: From<D::Error>,
+    {
+        self.manifest.add_build_dependency(name, spec)?;
 
-        assert!(path.exists());
-        let path = path.join("Cargo.toml");
-        assert!(path.exists());
+        Ok(self)
+    }
 
-        project.clear();
+    /// Sets `package.workspace`, the path from this crate to the root of the workspace it is a
+    /// member of, e.g. `"../.."`. Only needed when the crate isn't inside the workspace dir.
+    pub fn workspace_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.manifest.package.workspace_path = Some(path.into());
+
+        self
     }
 
-    #[test]
-    fn project_empty_build_creates_dir() {
-        let path = PathBuf::from("tmp");
-        let project = GenericBuilder::new(&path).build().unwrap();
-        assert!(path.exists());
-        project.clear();
+    /// Sets `package.links`. Cargo rejects the manifest unless the package also has a build script,
+    /// e.g. a `build.rs` added with `add_dir()`.
+    pub fn links(mut self, links: &str) -> Self {
+        self.manifest.package.links = Some(links.to_owned());
+
+        self
     }
 
-    #[test]
-    fn project_with_dir_and_files_works() {
-        let path = PathBuf::from("tmp2");
-        let project = GenericBuilder::new(&path)
-            .add_dir(Dir::new("tmp2/foo").add_file("bar", &vec![101u8]))
-            .build().unwrap();
+    /// Adds `files` (paths relative to `target/`, with their contents) to the project's `target/`
+    /// dir, e.g. to test how a tool handles existing build artifacts. `target/` isn't treated
+    /// specially by the builders, so this is the same as adding the files with `add_dir()`.
+    pub fn seed_target(mut self, files: &[(&str, &[u8])]) -> Self {
+        let target = self.path.join("target");
+        for &(rel, contents) in files {
+            let path = target.join(rel);
+            let dir = Dir::new(path.parent().unwrap_or(&target));
+            self.project
+                .dirs
+                .push(dir.add_file(path.file_name().unwrap_or_default(), contents));
+        }
 
-        assert!(path.exists());
-        let path = path.join("foo");
-        assert!(path.exists());
-        let path = path.join("bar");
-        assert!(path.exists());
-//        project.clear();
+        self
     }
 
-}
+    /// Adds a benchmark at `benches/<name>.rs`. Without `contents`, a stub is written that builds
+    /// on stable: `fn main() {}` when `harness` is `Some(false)`, and an empty file otherwise.
+    /// If `harness` is set, a `[[bench]]` table with that `harness` value is added too.
+    pub fn add_bench(mut self, name: &str, contents: Option<&str>, harness: Option<bool>) -> Self {
+        let contents = contents.unwrap_or(match harness {
+            Some(false) => "fn main() {}\n",
+            _ => "",
+        });
+        if harness.is_some() {
+            self.manifest.benches.push(BenchTarget {
+                name: name.to_owned(),
+                harness,
+                ..BenchTarget::default()
+            });
+        }
+
+        let benches = Dir::new(self.path.join("benches"));
+        self.add_dir(benches.add_file(format!("{}.rs", name), contents.as_bytes()))
+    }
 
+    /// Adds a `[[test]]` table for the test `name` at `path`, relative to the crate root. Unless
+    /// the project already has a file there, a stub is written too: `fn main() {}` for a custom
+    /// harness (`harness` false), and an empty file otherwise.
+    pub fn test_target(mut self, name: &str, path: &str, harness: bool) -> Self {
+        self.manifest.tests.push(TestTarget {
+            name: name.to_owned(),
+            path: Some(path.to_owned()),
+            harness: Some(harness),
+        });
 
+        if self.project.file_mut(&self.path.join(path)).is_some() {
+            return self;
+        }
+        let contents: &[u8] = if harness { b"" } else { b"fn main() {}\n" };
+        let root = Dir::new(&self.path);
+        self.add_dir(root.add_file(path, contents))
+    }
+
+    /// Adds a binary at `src/bin/<name>.rs` for cargo to discover without a `[[bin]]` table.
+    /// Without `contents`, the binary is `fn main() {}`.
+    pub fn add_bin_src(self, name: &str, contents: Option<&str>) -> Self {
+        let contents = contents.unwrap_or("fn main() {}\n");
+        let bin = Dir::new(self.path.join("src/bin"));
+        self.add_dir(bin.add_file(format!("{}.rs", name), contents.as_bytes()))
+    }
+
+    /// Adds the layout `cargo new` would create for a crate with both a library and a binary:
+    /// `src/lib.rs`, a hello world `src/main.rs`, a `README.md` and a `.gitignore` ignoring
+    /// `/target`, plus a `Cargo.toml` unless one was added already. The package is named after
+    /// the project dir if that is a valid crate name, and `skeleton` otherwise.
+    ///
+    /// Later calls override single pieces: a manifest from `add_cargo_toml()` replaces the
+    /// default one, and files added at the same paths are written after these, so they win.
+    pub fn skeleton(mut self) -> Self {
+        let name = self
+            .path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .filter(|name| {
+                name.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            })
+            .unwrap_or("skeleton")
+            .to_owned();
+        if !self.cargo_toml {
+            self.manifest = Manifest::try_from(&name, "0.1.0", &[], None, None)
+                .expect("the skeleton manifest is valid");
+            self.cargo_toml = true;
+        }
+        let src = Dir::new(self.path.join("src"))
+            .add_file("lib.rs", b"")
+            .add_file(
+                "main.rs",
+                b"fn main() {\n    println!(\"Hello, world!\");\n}\n",
+            );
+
+        self.root_file("README.md", format!("# {}\n", name))
+            .root_file(".gitignore", "/target\n")
+            .add_dir(src)
+    }
+
+    /// Turns the builder into a `GenericBuilder`. The `Cargo.toml` and modules are added to the
+    /// project as plain files first, so building either builder creates the same tree.
+    pub fn into_generic(mut self) -> Result<GenericBuilder, EphemeralError> {
+        self.materialize()?;
+
+        Ok(GenericBuilder::from_project(self.project))
+    }
+
+    /// Adds the files generated from the manifest and modules to the project.
+    fn materialize(&mut self) -> Result<(), EphemeralError> {
+        if self.cargo_toml {
+            let contents = self.manifest_bytes()?;
+            self.project
+                .root_mut()
+                .files
+                .push(File::new(self.path.join("Cargo.toml"), &contents));
+            self.cargo_toml = false;
+        }
+        if self.offline_lock {
+            let lockfile = self.manifest.offline_lockfile()?;
+            self.project
+                .root_mut()
+                .files
+                .push(File::new(self.path.join("Cargo.lock"), lockfile.as_bytes()));
+            self.offline_lock = false;
+        }
+        if self.scratch_registry {
+            self.add_scratch_registry()?;
+            self.scratch_registry = false;
+        }
+        self.add_modules();
+
+        Ok(())
+    }
+
+    /// Adds a module at `path` (e.g. `"a"` or `"b/c"`, `"b::c"` also works) under `src/` with the
+    /// given contents, and declares it with `mod` in its parent, all the way up to `lib.rs`.
+    ///
+    /// The files are generated at build time: a leaf module is written to `src/<path>.rs`, parent
+    /// modules that weren't added explicitly are created empty, and modules with children are
+    /// written to `<name>/mod.rs` on the 2015 edition and `<name>.rs` otherwise. If the project
+    /// already has a `src/lib.rs`, the declarations are appended to it.
+    pub fn module(mut self, path: &str, contents: &str) -> Self {
+        self.modules
+            .insert(path.replace("::", "/"), contents.to_owned());
+
+        self
+    }
+
+    fn add_modules(&mut self) {
+        if self.modules.is_empty() {
+            return;
+        }
+
+        let mut modules = self.modules.clone();
+        for path in self.modules.keys() {
+            let mut parent = Path::new(path).parent();
+            while let Some(path) = parent.filter(|path| !path.as_os_str().is_empty()) {
+                modules
+                    .entry(path.to_string_lossy().into_owned())
+                    .or_default();
+                parent = path.parent();
+            }
+        }
+
+        let declarations = |parent: &Path| -> String {
+            modules
+                .keys()
+                .map(Path::new)
+                .filter(|module| module.parent() == Some(parent))
+                .filter_map(|module| module.file_name())
+                .map(|name| format!("mod {};\n", name.to_string_lossy()))
+                .collect()
+        };
+
+        let src = self.path.join("src");
+        for (module, contents) in modules.iter() {
+            let children = declarations(Path::new(module));
+            let path =
+                if !children.is_empty() && self.manifest.package.edition == Edition::Edition2015 {
+                    src.join(module).join("mod.rs")
+                } else {
+                    src.join(format!("{}.rs", module))
+                };
+
+            let mut contents = contents.clone();
+            if !contents.is_empty() && !contents.ends_with('\n') {
+                contents.push('\n');
+            }
+            contents.push_str(&children);
+
+            let dir = Dir::new(path.parent().unwrap_or(&src));
+            self.project
+                .dirs
+                .push(dir.add_file(path.file_name().unwrap_or_default(), contents.as_bytes()));
+        }
+
+        let root = declarations(Path::new(""));
+        match self
+            .project
+            .file_mut(&src.join("lib.rs"))
+            .and_then(|file| file.bytes_mut())
+        {
+            Some(bytes) => bytes.extend_from_slice(root.as_bytes()),
+            None => self
+                .project
+                .dirs
+                .push(Dir::new(&src).add_file("lib.rs", root.as_bytes())),
+        }
+    }
+}
+
+/// Same as `RustBuilder::scratch()`.
+impl Default for RustBuilder {
+    fn default() -> Self {
+        RustBuilder::scratch()
+    }
+}
+
+impl Builder for RustBuilder {
+    fn build(mut self) -> Result<Project, EphemeralError> {
+        self.materialize()?;
+        self.project.create_dirs()?;
+
+        Ok(self.project.guard_if_clearing())
+    }
+
+    #[cfg(feature = "tar")]
+    fn build_tar<W: Write>(mut self, writer: W) -> Result<(), EphemeralError> {
+        self.materialize()?;
+        let mut tar = TarWriter::new(&self.path, writer);
+        tar.add_project(&self.project)?;
+
+        tar.finish()
+    }
+
+    #[cfg(feature = "zip")]
+    fn build_zip<W: Write>(mut self, writer: W) -> Result<(), EphemeralError> {
+        self.materialize()?;
+        let mut zip = ZipWriter::new(&self.path, writer);
+        zip.add_project(&self.project)?;
+
+        zip.finish()
+    }
+
+    fn project(&self) -> &Project {
+        &self.project
+    }
+
+    fn project_mut(&mut self) -> &mut Project {
+        &mut self.project
+    }
+
+    fn into_project(self) -> Project {
+        self.project
+    }
+}
+
+/// Builds a cargo workspace: a root `Cargo.toml` with a `[workspace]` table and a crate for each
+/// member added with `member()`.
+#[derive(Clone, Debug)]
+pub struct WorkspaceBuilder {
+    path: PathBuf,
+    project: Project,
+    manifest: WorkspaceManifest,
+    members: Vec<RustBuilder>,
+}
+
+impl WorkspaceBuilder {
+    pub fn new<T>(path: T) -> WorkspaceBuilder
+    where
+        T: Into<PathBuf> + Clone,
+    {
+        WorkspaceBuilder {
+            project: Project::new(path.clone()),
+            path: path.into(),
+            manifest: WorkspaceManifest::default(),
+            members: vec![],
+        }
+    }
+
+    /// Adds a member crate in the `name` dir of the workspace. The crate's builder is passed to
+    /// `f` to be populated.
+    pub fn member<F>(mut self, name: &str, f: F) -> Self
+    where
+        F: FnOnce(RustBuilder) -> RustBuilder,
+    {
+        self.manifest.workspace.members.push(name.to_owned());
+        self.members.push(f(RustBuilder::new(self.path.join(name))));
+
+        self
+    }
+
+    /// Adds `path` to the `exclude` list, keeping that dir out of the workspace.
+    pub fn exclude(mut self, path: &str) -> Self {
+        self.manifest.workspace.exclude.push(path.to_owned());
+
+        self
+    }
+
+    /// Adds `name` to `default-members`, the members cargo builds when run in the workspace root
+    /// without `-p`. `name` must also be added with `member()`, or `build()` fails.
+    pub fn default_member(mut self, name: &str) -> Self {
+        self.manifest
+            .workspace
+            .default_members
+            .push(name.to_owned());
+
+        self
+    }
+
+    /// Sets `version` in `[workspace.package]`, for members built with
+    /// `ConfigBuilder::version_from_workspace()`.
+    pub fn package_version(mut self, version: &str) -> Result<Self, EphemeralError> {
+        self.manifest
+            .workspace
+            .package
+            .get_or_insert_with(Default::default)
+            .version = Some(semver::Version::from_str(version)?);
+
+        Ok(self)
+    }
+
+    /// Sets the free-form `[workspace.metadata]` table.
+    pub fn metadata(mut self, metadata: toml::Value) -> Self {
+        self.manifest.workspace.metadata = Some(metadata);
+
+        self
+    }
+
+    /// Sets the level of the `tool` lint `name` in `[workspace.lints]`, e.g.
+    /// `lint("clippy", "pedantic", LintLevel::Warn)`.
+    pub fn lint(mut self, tool: &str, name: &str, level: LintLevel) -> Self {
+        self.manifest
+            .workspace
+            .lints
+            .get_or_insert_with(Default::default)
+            .entry(tool.to_owned())
+            .or_default()
+            .insert(name.to_owned(), level);
+
+        self
+    }
+
+    /// Serializes the root manifest that is written as the workspace's `Cargo.toml`.
+    pub fn manifest_toml(&self) -> Result<String, EphemeralError> {
+        rust_tools::to_toml(&self.manifest)
+    }
+
+    /// Returns `EphemeralError::DuplicatePackageName` with the sorted package names that more than
+    /// one member with a `Cargo.toml` uses.
+    fn check_package_names(&self) -> Result<(), EphemeralError> {
+        let mut counts = BTreeMap::new();
+        for member in self.members.iter().filter(|member| member.cargo_toml) {
+            *counts.entry(&member.manifest.package.name).or_insert(0) += 1;
+        }
+        let duplicates: Vec<String> = counts
+            .into_iter()
+            .filter(|&(_, count)| count > 1)
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        if duplicates.is_empty() {
+            Ok(())
+        } else {
+            Err(EphemeralError::DuplicatePackageName(duplicates))
+        }
+    }
+
+    /// Checks the members and adds the root `Cargo.toml` to the project.
+    fn add_root_manifest(&mut self) -> Result<(), EphemeralError> {
+        let workspace = &self.manifest.workspace;
+        if let Some(unknown) = workspace
+            .default_members
+            .iter()
+            .find(|name| !workspace.members.contains(name))
+        {
+            return Err(EphemeralError::UnknownMember(unknown.clone()));
+        }
+        self.check_package_names()?;
+        let contents = self.manifest_toml()?;
+        self.project
+            .root_mut()
+            .files
+            .push(File::new(self.path.join("Cargo.toml"), contents.as_bytes()));
+
+        Ok(())
+    }
+}
+
+impl Builder for WorkspaceBuilder {
+    fn build(mut self) -> Result<Project, EphemeralError> {
+        self.add_root_manifest()?;
+        self.project.create_dirs()?;
+        // Members are cleared along with the workspace, if at all, so their guards are dropped.
+        for member in self.members {
+            member.build()?.unguard();
+        }
+
+        Ok(self.project.guard_if_clearing())
+    }
+
+    #[cfg(feature = "tar")]
+    fn build_tar<W: Write>(mut self, writer: W) -> Result<(), EphemeralError> {
+        self.add_root_manifest()?;
+        let mut tar = TarWriter::new(&self.path, writer);
+        tar.add_project(&self.project)?;
+        for mut member in self.members {
+            member.materialize()?;
+            tar.add_project(&member.project)?;
+        }
+
+        tar.finish()
+    }
+
+    #[cfg(feature = "zip")]
+    fn build_zip<W: Write>(mut self, writer: W) -> Result<(), EphemeralError> {
+        self.add_root_manifest()?;
+        let mut zip = ZipWriter::new(&self.path, writer);
+        zip.add_project(&self.project)?;
+        for mut member in self.members {
+            member.materialize()?;
+            zip.add_project(&member.project)?;
+        }
+
+        zip.finish()
+    }
+
+    fn project(&self) -> &Project {
+        &self.project
+    }
+
+    fn project_mut(&mut self) -> &mut Project {
+        &mut self.project
+    }
+
+    fn into_project(self) -> Project {
+        self.project
+    }
+}
+
+/// Builds several crates under a common root, each in the dir named after it, with path
+/// dependencies between them declared through `depends_on()` and `dev_depends_on()`.
+#[derive(Clone, Debug)]
+pub struct CrateGraph {
+    path: PathBuf,
+    project: Project,
+    crates: Vec<(String, RustBuilder)>,
+    dependencies: Vec<(String, String)>,
+    dev_dependencies: Vec<(String, String)>,
+}
+
+impl CrateGraph {
+    pub fn new<T>(path: T) -> CrateGraph
+    where
+        T: Into<PathBuf> + Clone,
+    {
+        CrateGraph {
+            project: Project::new(path.clone()),
+            path: path.into(),
+            crates: vec![],
+            dependencies: vec![],
+            dev_dependencies: vec![],
+        }
+    }
+
+    /// Adds a library crate called `name` in the `name` dir of the root, with a `Cargo.toml` and
+    /// an empty `src/lib.rs`. Its builder is passed to `f` to be populated further.
+    pub fn add_crate<F>(mut self, name: &str, f: F) -> Result<Self, EphemeralError>
+    where
+        F: FnOnce(RustBuilder) -> RustBuilder,
+    {
+        let path = self.path.join(name);
+        let builder = RustBuilder::new(&path)
+            .add_cargo_toml(Manifest::try_from(name, "0.1.0", &[], None, None)?)?
+            .add_dir(Dir::new(path.join("src")).add_file("lib.rs", b""));
+        self.crates.push((name.to_owned(), f(builder)));
+
+        Ok(self)
+    }
+
+    /// Makes crate `from` depend on crate `to` through a relative `path` dependency. These
+    /// dependencies must not form a cycle, or `build()` fails with
+    /// `EphemeralError::DependencyCycle`.
+    pub fn depends_on(mut self, from: &str, to: &str) -> Self {
+        self.dependencies.push((from.to_owned(), to.to_owned()));
+
+        self
+    }
+
+    /// Like `depends_on()`, but adds `to` to the `[dev-dependencies]` of `from`. Cargo allows
+    /// cycles through dev-dependencies, e.g. `b` dev-depending on `a` which depends on `b`, so
+    /// these edges are left out of the cycle check.
+    pub fn dev_depends_on(mut self, from: &str, to: &str) -> Self {
+        self.dev_dependencies.push((from.to_owned(), to.to_owned()));
+
+        self
+    }
+
+    /// Adds the `path` dependencies declared with `depends_on()` and `dev_depends_on()` to the
+    /// crates' manifests, after checking that the regular ones don't form a cycle.
+    fn link_crates(&mut self) -> Result<(), EphemeralError> {
+        let edges = self
+            .dependencies
+            .iter()
+            .map(|edge| (edge, false))
+            .chain(self.dev_dependencies.iter().map(|edge| (edge, true)));
+        for ((from, to), _) in edges.clone() {
+            for &name in [from, to].iter() {
+                if !self.crates.iter().any(|(crate_name, _)| crate_name == name) {
+                    return Err(EphemeralError::UnknownCrate(name.clone()));
+                }
+            }
+        }
+        if let Some(cycle) = find_cycle(&self.dependencies) {
+            return Err(EphemeralError::DependencyCycle(cycle));
+        }
+
+        for ((from, to), dev) in edges {
+            let (_, builder) = self
+                .crates
+                .iter_mut()
+                .find(|(name, _)| name == from)
+                .expect("crates were checked above");
+            let dependency = Dependency::path(format!("../{}", to));
+            if dev {
+                builder.manifest.add_dev_dependency(to, dependency)?;
+            } else {
+                builder
+                    .manifest
+                    .dependencies_mut()
+                    .insert(to.clone(), dependency);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Finds a cycle in the `(from, to)` edges, returned as the crates along it with the first one
+/// repeated at the end, e.g. `["a", "b", "a"]`.
+fn find_cycle(edges: &[(String, String)]) -> Option<Vec<String>> {
+    fn visit<'a>(
+        node: &'a str,
+        edges: &'a [(String, String)],
+        path: &mut Vec<&'a str>,
+        done: &mut Vec<&'a str>,
+    ) -> Option<Vec<String>> {
+        if let Some(start) = path.iter().position(|&seen| seen == node) {
+            let mut cycle: Vec<_> = path[start..].iter().map(|&name| name.to_owned()).collect();
+            cycle.push(node.to_owned());
+            return Some(cycle);
+        }
+        if done.contains(&node) {
+            return None;
+        }
+        path.push(node);
+        for (_, to) in edges.iter().filter(|(from, _)| from == node) {
+            if let Some(cycle) = visit(to, edges, path, done) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        done.push(node);
+
+        None
+    }
+
+    let mut done = vec![];
+    edges
+        .iter()
+        .find_map(|(from, _)| visit(from, edges, &mut vec![], &mut done))
+}
+
+impl Builder for CrateGraph {
+    fn build(mut self) -> Result<Project, EphemeralError> {
+        self.link_crates()?;
+        self.project.create_dirs()?;
+        for (_, builder) in self.crates {
+            builder.build()?.unguard();
+        }
+
+        Ok(self.project.guard_if_clearing())
+    }
+
+    #[cfg(feature = "tar")]
+    fn build_tar<W: Write>(mut self, writer: W) -> Result<(), EphemeralError> {
+        self.link_crates()?;
+        let mut tar = TarWriter::new(&self.path, writer);
+        tar.add_project(&self.project)?;
+        for (_, mut builder) in self.crates {
+            builder.materialize()?;
+            tar.add_project(&builder.project)?;
+        }
+
+        tar.finish()
+    }
+
+    #[cfg(feature = "zip")]
+    fn build_zip<W: Write>(mut self, writer: W) -> Result<(), EphemeralError> {
+        self.link_crates()?;
+        let mut zip = ZipWriter::new(&self.path, writer);
+        zip.add_project(&self.project)?;
+        for (_, mut builder) in self.crates {
+            builder.materialize()?;
+            zip.add_project(&builder.project)?;
+        }
+
+        zip.finish()
+    }
+
+    fn project(&self) -> &Project {
+        &self.project
+    }
+
+    fn project_mut(&mut self) -> &mut Project {
+        &mut self.project
+    }
+
+    fn into_project(self) -> Project {
+        self.project
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rust_tools::{Edition, Manifest};
+    use std::collections::BTreeSet;
+    #[test]
+    fn rust_builder_creates_rust_project() {
+        let path = PathBuf::from("foo");
+        let config = Manifest::try_from(
+            "foo",
+            "0.1.0",
+            &["foo <foo@bar.com>"],
+            Some(Edition::Edition2018),
+            None,
+        )
+        .unwrap();
+        let project = RustBuilder::new(&path)
+            .add_cargo_toml(config)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert!(path.exists());
+        let path = path.join("Cargo.toml");
+        assert!(path.exists());
+
+        project.clear();
+    }
+
+    #[test]
+    fn manifest_toml_matches_manifest() {
+        let config =
+            Manifest::try_from("foo", "0.1.0", &["foo <foo@bar.com>"], None, None).unwrap();
+        let toml = RustBuilder::new("unbuilt")
+            .add_cargo_toml(config)
+            .unwrap()
+            .manifest_toml()
+            .unwrap();
+
+        assert!(toml.contains("name = \"foo\""));
+        assert!(toml.contains("version = \"0.1.0\""));
+        assert!(toml.contains("edition = \"2021\""));
+    }
+
+    #[test]
+    fn edition_set_after_cargo_toml_is_written() {
+        let path = PathBuf::from("tmp_edition");
+        let project = RustBuilder::new(&path)
+            .add_cargo_toml(Manifest::try_from("foo", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .edition(Edition::Edition2015)
+            .build()
+            .unwrap();
+
+        let contents = std::fs::read_to_string(path.join("Cargo.toml")).unwrap();
+        assert!(contents.contains("edition = \"2015\""));
+        project.clear();
+    }
+
+    #[test]
+    fn edition_2024_crates_build() {
+        let project = RustBuilder::new("tmp_edition_2024")
+            .add_cargo_toml(Manifest::try_from("new", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .edition(Edition::Edition2024)
+            .add_dir(Dir::new("tmp_edition_2024/src").add_file("lib.rs", b"pub fn f() {}"))
+            .build()
+            .unwrap();
+
+        project.assert_cargo_check();
+        let output = project
+            .cargo_offline(["metadata", "--no-deps", "--format-version", "1"])
+            .unwrap();
+        assert!(String::from_utf8_lossy(&output.stdout).contains("\"edition\":\"2024\""));
+        let manifest = std::fs::read_to_string(project.manifest_path()).unwrap();
+        assert_eq!(
+            Manifest::from_toml_str(&manifest).unwrap().package.edition,
+            Edition::Edition2024
+        );
+        project.clear();
+    }
+
+    #[test]
+    fn resolver_is_serialized_and_validated() {
+        let builder = RustBuilder::new("unbuilt").resolver("2").unwrap();
+        assert!(builder
+            .manifest_toml()
+            .unwrap()
+            .contains("resolver = \"2\""));
+
+        assert!(!RustBuilder::new("unbuilt")
+            .manifest_toml()
+            .unwrap()
+            .contains("resolver"));
+        assert!(RustBuilder::new("unbuilt").resolver("4").is_err());
+    }
+
+    #[test]
+    fn manifest_toml_is_stable() {
+        let deps = ["b", "c", "a"]
+            .iter()
+            .map(|name| (name.to_string(), semver::Version::new(1, 0, 0)))
+            .collect();
+        let manifest = Manifest::try_from("foo", "0.1.0", &[], None, Some(deps)).unwrap();
+        let toml = RustBuilder::new("unbuilt")
+            .add_cargo_toml(manifest)
+            .unwrap()
+            .manifest_toml()
+            .unwrap();
+
+        assert!(toml.ends_with("[dependencies]\na = \"1.0.0\"\nb = \"1.0.0\"\nc = \"1.0.0\"\n"));
+        assert!(!toml.ends_with("\n\n"));
+    }
+
+    #[test]
+    fn modules_are_declared_in_their_parents() {
+        let path = PathBuf::from("tmp_modules");
+        let project = RustBuilder::new(&path)
+            .add_dir(Dir::new("tmp_modules/src").add_file("lib.rs", b"pub fn root() {}\n"))
+            .module("a", "pub fn a() {}")
+            .module("b::c", "pub fn c() {}")
+            .build()
+            .unwrap();
+
+        let read = |rel: &str| std::fs::read_to_string(path.join(rel)).unwrap();
+        assert_eq!(read("src/lib.rs"), "pub fn root() {}\nmod a;\nmod b;\n");
+        assert_eq!(read("src/a.rs"), "pub fn a() {}\n");
+        assert_eq!(read("src/b.rs"), "mod c;\n");
+        assert_eq!(read("src/b/c.rs"), "pub fn c() {}\n");
+        project.clear();
+    }
+
+    #[test]
+    fn modules_with_children_use_mod_rs_on_2015() {
+        let path = PathBuf::from("tmp_modules_2015");
+        let project = RustBuilder::new(&path)
+            .edition(Edition::Edition2015)
+            .module("b/c", "")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(path.join("src/lib.rs")).unwrap(),
+            "mod b;\n"
+        );
+        assert!(path.join("src/b/mod.rs").exists());
+        assert!(path.join("src/b/c.rs").exists());
+        assert!(!path.join("src/b.rs").exists());
+        project.clear();
+    }
+
+    #[test]
+    fn layout_paths_point_at_built_files() {
+        let project = WorkspaceBuilder::new("tmp_layout_paths")
+            .member("member", |member| {
+                member
+                    .add_cargo_toml(Manifest::try_from("member", "0.1.0", &[], None, None).unwrap())
+                    .unwrap()
+            })
+            .add_dir(Dir::new("tmp_layout_paths/src").add_file("lib.rs", b""))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            project.manifest_path(),
+            Path::new("tmp_layout_paths/Cargo.toml")
+        );
+        assert!(project.manifest_path().is_file());
+        assert!(project.src_dir().join("lib.rs").is_file());
+        assert_eq!(
+            project.member_manifest_path("member"),
+            Path::new("tmp_layout_paths/member/Cargo.toml")
+        );
+        assert!(project.member_manifest_path("member").is_file());
+        project.clear();
+    }
+
+    #[test]
+    fn scratch_builds_a_crate_in_a_fresh_temp_dir() {
+        let project = RustBuilder::scratch().build().unwrap();
+        let other = RustBuilder::default().build().unwrap();
+
+        assert!(project.path.starts_with(env::temp_dir()));
+        assert_ne!(project.path, other.path);
+        assert!(project.manifest_path().is_file());
+        assert!(project.src_dir().join("lib.rs").is_file());
+        assert!(project
+            .cargo_offline(["metadata", "--no-deps", "--format-version", "1"])
+            .unwrap()
+            .status
+            .success());
+
+        let path = project.path.clone();
+        drop(project);
+        assert!(!path.exists());
+        other.clear();
+    }
+
+    #[test]
+    fn skeleton_is_a_valid_crate_with_overridable_pieces() {
+        let project = RustBuilder::new("tmp_skeleton").skeleton().build().unwrap();
+        let read = |rel: &str| std::fs::read_to_string(project.path.join(rel)).unwrap();
+        assert!(read("Cargo.toml").contains("name = \"tmp_skeleton\""));
+        assert_eq!(read("README.md"), "# tmp_skeleton\n");
+        assert_eq!(read(".gitignore"), "/target\n");
+        project.assert_cargo_check();
+        project.clear();
+
+        let project = RustBuilder::new("tmp_skeleton.2")
+            .skeleton()
+            .add_cargo_toml(Manifest::try_from("custom", "0.2.0", &[], None, None).unwrap())
+            .unwrap()
+            .root_file("README.md", "custom")
+            .add_dir(Dir::new("tmp_skeleton.2/src").add_file("lib.rs", b"pub fn custom() {}"))
+            .build()
+            .unwrap();
+        let read = |rel: &str| std::fs::read_to_string(project.path.join(rel)).unwrap();
+        assert!(read("Cargo.toml").contains("name = \"custom\""));
+        assert_eq!(read("README.md"), "custom");
+        assert_eq!(read("src/lib.rs"), "pub fn custom() {}");
+        assert!(read("src/main.rs").contains("Hello, world!"));
+        project.clear();
+    }
+
+    #[test]
+    fn raw_manifest_is_written_unchanged() {
+        let broken = "[package]\nversion = \"not.a.version\"\nunknown-key = 1\n";
+        let project = RustBuilder::new("tmp_raw_manifest")
+            .add_cargo_toml(Manifest::try_from("typed", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .raw_manifest(broken)
+            .add_dir(Dir::new("tmp_raw_manifest/src").add_file("lib.rs", b""))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read(project.manifest_path()).unwrap(),
+            broken.as_bytes()
+        );
+        let output = project
+            .cargo_offline(["metadata", "--no-deps", "--format-version", "1"])
+            .unwrap();
+        assert!(!output.status.success());
+        project.clear();
+    }
+
+    #[test]
+    fn members_inherit_the_workspace_version() {
+        let config = rust_tools::Config::builder()
+            .name("inheritor")
+            .version_from_workspace()
+            .build()
+            .unwrap();
+        let project = WorkspaceBuilder::new("tmp_inherit_version")
+            .package_version("2.3.4")
+            .unwrap()
+            .member("inheritor", |member| {
+                member
+                    .add_cargo_toml(Manifest::new(config, None))
+                    .unwrap()
+                    .add_dir(Dir::new("tmp_inherit_version/inheritor/src").add_file("lib.rs", b""))
+            })
+            .build()
+            .unwrap();
+
+        let root = std::fs::read_to_string(project.manifest_path()).unwrap();
+        assert!(root.contains("[workspace.package]\nversion = \"2.3.4\"\n"));
+        let member = std::fs::read_to_string(project.member_manifest_path("inheritor")).unwrap();
+        assert!(!member.contains("version = "));
+        assert!(member.contains("[package.version]\nworkspace = true\n"));
+        let output = project
+            .cargo_offline(["metadata", "--no-deps", "--format-version", "1"])
+            .unwrap();
+        assert!(String::from_utf8_lossy(&output.stdout).contains("\"version\":\"2.3.4\""));
+        project.clear();
+    }
+
+    #[test]
+    fn workspace_exclude_keeps_dirs_out_of_workspace() {
+        let crate_manifest =
+            |name: &str| Manifest::try_from(name, "0.1.0", &[], None, None).unwrap();
+        let workspace = WorkspaceBuilder::new("tmp_workspace")
+            .member("a", |a| {
+                a.add_cargo_toml(crate_manifest("a"))
+                    .unwrap()
+                    .add_dir(Dir::new("tmp_workspace/a/src").add_file("lib.rs", b""))
+            })
+            .exclude("b");
+        assert!(workspace
+            .manifest_toml()
+            .unwrap()
+            .contains("[workspace]\nmembers = [\"a\"]\nexclude = [\"b\"]\n"));
+
+        let project = workspace.build().unwrap();
+        let excluded = RustBuilder::new("tmp_workspace/b")
+            .add_cargo_toml(crate_manifest("b"))
+            .unwrap()
+            .add_dir(Dir::new("tmp_workspace/b/src").add_file("lib.rs", b""))
+            .build()
+            .unwrap();
+
+        let output = excluded
+            .cargo([
+                "metadata",
+                "--offline",
+                "--no-deps",
+                "--format-version",
+                "1",
+            ])
+            .unwrap();
+        assert!(output.status.success());
+        let metadata = String::from_utf8(output.stdout).unwrap();
+        assert!(metadata.contains("tmp_workspace/b\""));
+        assert!(!metadata.contains("\"name\":\"a\""));
+
+        project.clear();
+    }
+
+    #[test]
+    fn workspace_default_members_must_be_members() {
+        let workspace = WorkspaceBuilder::new("tmp_default_members")
+            .member("a", |a| a)
+            .member("b", |b| b)
+            .default_member("a");
+        assert!(workspace
+            .manifest_toml()
+            .unwrap()
+            .contains("members = [\"a\", \"b\"]\ndefault-members = [\"a\"]\n"));
+
+        match workspace.default_member("c").build() {
+            Err(EphemeralError::UnknownMember(name)) => assert_eq!(name, "c"),
+            other => panic!("expected UnknownMember, got {:?}", other),
+        }
+        assert!(!Path::new("tmp_default_members").exists());
+    }
+
+    #[test]
+    fn workspace_rejects_duplicate_package_names() {
+        let named = |name: &str| {
+            let manifest = Manifest::try_from(name, "0.1.0", &[], None, None).unwrap();
+            move |member: RustBuilder| member.add_cargo_toml(manifest).unwrap()
+        };
+        let result = WorkspaceBuilder::new("tmp_duplicate_names")
+            .member("a", named("foo"))
+            .member("b", named("foo"))
+            .member("c", named("bar"))
+            .member("d", |d| d)
+            .member("e", |e| e)
+            .build();
+
+        match result {
+            Err(EphemeralError::DuplicatePackageName(names)) => assert_eq!(names, ["foo"]),
+            other => panic!("expected DuplicatePackageName, got {:?}", other),
+        }
+        assert!(!Path::new("tmp_duplicate_names").exists());
+    }
+
+    #[test]
+    fn workspace_manifest_omits_empty_exclude() {
+        let toml = WorkspaceBuilder::new("unbuilt")
+            .member("a", |a| a)
+            .manifest_toml()
+            .unwrap();
+
+        assert_eq!(toml, "[workspace]\nmembers = [\"a\"]\n");
+    }
+
+    #[test]
+    fn workspace_members_clearing_on_drop_survive_the_build() {
+        let project = WorkspaceBuilder::new("tmp_workspace_drop")
+            .member("a", |a| {
+                a.add_cargo_toml(Manifest::try_from("a", "0.1.0", &[], None, None).unwrap())
+                    .unwrap()
+                    .add_dir(Dir::new("tmp_workspace_drop/a/src").add_file("lib.rs", b""))
+                    .clear_on_drop(true)
+            })
+            .build()
+            .unwrap();
+
+        assert!(project.path.join("a/Cargo.toml").exists());
+        assert!(project.path.join("a/src/lib.rs").exists());
+        project.clear();
+    }
+
+    #[test]
+    fn raw_manifest_tables_are_appended() {
+        let toml = RustBuilder::new("unbuilt")
+            .raw_manifest_table("lints.rust", "unsafe_code = \"forbid\"")
+            .unwrap()
+            .raw_manifest_table("profile.release", "lto = true\n\n")
+            .unwrap()
+            .manifest_toml()
+            .unwrap();
+
+        assert!(toml.ends_with(
+            "edition = \"2021\"\n\n[lints.rust]\nunsafe_code = \"forbid\"\n\n\
+             [profile.release]\nlto = true\n"
+        ));
+        assert!(RustBuilder::new("unbuilt")
+            .raw_manifest_table("lints", "unsafe_code = ")
+            .is_err());
+    }
+
+    #[test]
+    fn project_into_builder_rebuilds_the_same_tree() {
+        let path = PathBuf::from("tmp_round_trip");
+        let project = GenericBuilder::new(&path)
+            .add_dir(Dir::new(&path).dir("src", |src| src.add_file("lib.rs", b"")))
+            .build()
+            .unwrap();
+        let copy = project.clone();
+        project.clear();
+
+        let project = copy
+            .into_builder()
+            .add_dir(Dir::new(&path).add_file("README.md", b""))
+            .build()
+            .unwrap();
+        assert!(path.join("src/lib.rs").exists());
+        assert!(path.join("README.md").exists());
+        project.clear();
+    }
+
+    #[test]
+    fn project_into_rust_builder_replaces_manifest() {
+        let path = PathBuf::from("tmp_round_trip_rust");
+        let manifest = |name: &str| Manifest::try_from(name, "0.1.0", &[], None, None).unwrap();
+        let project = RustBuilder::new(&path)
+            .add_cargo_toml(manifest("foo"))
+            .unwrap()
+            .module("foo", "")
+            .build()
+            .unwrap();
+
+        let project = project
+            .into_rust_builder(manifest("bar"))
+            .unwrap()
+            .build()
+            .unwrap();
+        let contents = std::fs::read_to_string(path.join("Cargo.toml")).unwrap();
+        assert!(contents.contains("name = \"bar\""));
+        assert!(!contents.contains("name = \"foo\""));
+        assert_eq!(project.dirs[0].files.len(), 1);
+        assert!(path.join("src/foo.rs").exists());
+        project.clear();
+    }
+
+    #[test]
+    fn crate_graph_wires_path_dependencies() {
+        let project = CrateGraph::new("tmp_graph")
+            .add_crate("a", |a| a)
+            .and_then(|graph| graph.add_crate("b", |b| b))
+            .and_then(|graph| graph.add_crate("c", |c| c.module("util", "")))
+            .unwrap()
+            .depends_on("a", "b")
+            .depends_on("b", "c")
+            .build()
+            .unwrap();
+
+        let manifest = |name: &str| {
+            std::fs::read_to_string(project.path.join(name).join("Cargo.toml")).unwrap()
+        };
+        assert!(manifest("a").contains("[dependencies.b]\npath = \"../b\"\n"));
+        assert!(manifest("b").contains("[dependencies.c]\npath = \"../c\"\n"));
+        assert!(!manifest("c").contains("[dependencies"));
+
+        let a = Project::new(project.path.join("a"));
+        let output = a
+            .cargo(["metadata", "--offline", "--format-version", "1"])
+            .unwrap();
+        assert!(output.status.success());
+        let metadata = String::from_utf8(output.stdout).unwrap();
+        for name in ["a", "b", "c"].iter() {
+            assert!(metadata.contains(&format!("\"name\":\"{}\"", name)));
+        }
+
+        project.clear();
+    }
+
+    #[test]
+    fn crate_graph_allows_cycles_only_through_dev_dependencies() {
+        let graph = |name: &str| {
+            CrateGraph::new(name)
+                .add_crate("a", |a| a)
+                .and_then(|graph| graph.add_crate("b", |b| b))
+                .and_then(|graph| graph.add_crate("c", |c| c))
+                .unwrap()
+                .depends_on("a", "b")
+        };
+
+        let project = graph("tmp_graph_dev_cycle")
+            .dev_depends_on("b", "a")
+            .build()
+            .unwrap();
+        let manifest = std::fs::read_to_string(project.path.join("b/Cargo.toml")).unwrap();
+        assert!(manifest.contains("[dev-dependencies.a]\npath = \"../a\"\n"));
+        let output = Project::new(project.path.join("b"))
+            .cargo(["metadata", "--offline", "--format-version", "1"])
+            .unwrap();
+        assert!(output.status.success());
+        project.clear();
+
+        let result = graph("tmp_graph_cycle")
+            .depends_on("b", "c")
+            .depends_on("c", "a")
+            .build();
+        match result {
+            Err(EphemeralError::DependencyCycle(cycle)) => assert_eq!(cycle, ["a", "b", "c", "a"]),
+            other => panic!("expected DependencyCycle, got {:?}", other),
+        }
+        assert!(!Path::new("tmp_graph_cycle").exists());
+    }
+
+    #[test]
+    fn crate_graph_rejects_unknown_crates() {
+        let graph = CrateGraph::new("tmp_graph_unknown")
+            .add_crate("a", |a| a)
+            .unwrap()
+            .depends_on("a", "missing");
+
+        match graph.build() {
+            Err(EphemeralError::UnknownCrate(name)) => assert_eq!(name, "missing"),
+            other => panic!("expected UnknownCrate, got {:?}", other),
+        }
+        assert!(!Path::new("tmp_graph_unknown").exists());
+    }
+
+    #[test]
+    fn rename_path_moves_dirs_with_their_contents() {
+        let project = GenericBuilder::new("tmp_rename")
+            .add_dir(
+                Dir::new("tmp_rename")
+                    .add_file("top.txt", b"top")
+                    .dir("old", |old| {
+                        old.add_file("a.txt", b"a")
+                            .dir("inner", |inner| inner.add_file("b.txt", b"b"))
+                    })
+                    .dir("oldish", |dir| dir.add_file("c.txt", b"c")),
+            )
+            .rename_path("tmp_rename/old", "tmp_rename/new/place")
+            .rename_path("tmp_rename/top.txt", "tmp_rename/moved.txt")
+            .rename_path("tmp_rename/missing", "tmp_rename/anywhere")
+            .build()
+            .unwrap();
+
+        let read = |rel: &str| std::fs::read(project.path.join(rel)).unwrap();
+        assert_eq!(read("new/place/inner/b.txt"), b"b");
+        assert_eq!(read("new/place/a.txt"), b"a");
+        assert_eq!(read("moved.txt"), b"top");
+        assert_eq!(read("oldish/c.txt"), b"c");
+        assert!(project.is_absent("old"));
+        assert!(project.is_absent("top.txt"));
+        project.clear();
+    }
+
+    #[test]
+    fn with_dir_name_creates_named_dir_in_parent() {
+        let project = RustBuilder::with_dir_name("tmp_parent", "my-crate")
+            .add_cargo_toml(Manifest::try_from("my-crate", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(project.path, Path::new("tmp_parent/my-crate"));
+        assert_eq!(project.path.file_name().unwrap(), "my-crate");
+        assert!(project.path.join("Cargo.toml").exists());
+        project.clear();
+        assert!(!Path::new("tmp_parent").exists());
+    }
+
+    #[test]
+    fn target_discovery_flags_are_serialized_when_set() {
+        assert!(!RustBuilder::new("unbuilt")
+            .manifest_toml()
+            .unwrap()
+            .contains("auto"));
+
+        let toml = RustBuilder::new("unbuilt")
+            .autobins(false)
+            .autoexamples(false)
+            .autotests(true)
+            .autobenches(false)
+            .manifest_toml()
+            .unwrap();
+        assert!(toml.contains(
+            "autobins = false\nautoexamples = false\nautotests = true\nautobenches = false\n"
+        ));
+    }
+
+    #[test]
+    fn build_and_check_reports_cargo_diagnostics() {
+        let builder = |path: &str, lib: &[u8]| {
+            RustBuilder::new(path)
+                .add_cargo_toml(Manifest::try_from("checked", "0.1.0", &[], None, None).unwrap())
+                .unwrap()
+                .add_dir(Dir::new(path).dir("src", |src| src.add_file("lib.rs", lib)))
+        };
+
+        let project = builder("tmp_check_ok", b"pub fn ok() {}")
+            .build_and_check()
+            .unwrap();
+        assert!(project.path.join("target").exists());
+        project.clear();
+
+        match builder("tmp_check_err", b"pub fn broken() -> u8 {}").build_and_check() {
+            Err(EphemeralError::CargoFailed { status, stderr }) => {
+                assert!(!status.success());
+                assert!(stderr.contains("mismatched types"));
+            }
+            other => panic!("expected CargoFailed, got {:?}", other),
+        }
+        assert!(!Path::new("tmp_check_err").exists());
+    }
+
+    #[test]
+    fn seed_target_creates_artifacts() {
+        let project = RustBuilder::new("tmp_seed_target")
+            .seed_target(&[("debug/foo", b"artifact"), ("CACHEDIR.TAG", b"")])
+            .build()
+            .unwrap();
+
+        let target = project.path.join("target");
+        assert_eq!(
+            std::fs::read(target.join("debug/foo")).unwrap(),
+            b"artifact"
+        );
+        assert!(target.join("CACHEDIR.TAG").exists());
+        project.clear();
+    }
+
+    #[test]
+    fn generic_builder_from_spec_creates_described_project() {
+        let spec = ProjectSpec::from_toml_str(
+            r#"
+            path = "tmp_spec"
+
+            [manifest.package]
+            name = "foo"
+            version = "0.1.0"
+
+            [[dirs]]
+            path = "src"
+
+            [[dirs.files]]
+            path = "lib.rs"
+            contents = "pub fn foo() {}"
+
+            [[dirs]]
+            path = "assets"
+
+            [[dirs.files]]
+            path = "blob.bin"
+            contents = "base64:AAEC"
+            "#,
+        )
+        .unwrap();
+        let project = GenericBuilder::from_spec(spec).unwrap().build().unwrap();
+
+        let read = |rel: &str| std::fs::read(project.path.join(rel)).unwrap();
+        assert_eq!(read("src/lib.rs"), b"pub fn foo() {}");
+        assert_eq!(read("assets/blob.bin"), vec![0, 1, 2]);
+        assert!(String::from_utf8(read("Cargo.toml"))
+            .unwrap()
+            .contains("name = \"foo\""));
+        project.clear();
+    }
+
+    #[test]
+    fn generic_and_rust_builders_convert_into_each_other() {
+        let generic = GenericBuilder::new("tmp_convert")
+            .add_dir(Dir::new("tmp_convert/src").add_file("lib.rs", b"// lib\n"));
+
+        let rust = generic
+            .into_rust()
+            .add_cargo_toml(Manifest::try_from("converted", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .module("a", "pub fn a() {}\n");
+        assert!(rust
+            .project()
+            .plan()
+            .contains(&PathBuf::from("tmp_convert/src/lib.rs")));
+
+        let generic = rust.into_generic().unwrap();
+        assert_eq!(
+            generic.project().tree(),
+            "tmp_convert/\n  Cargo.toml\n  src/\n    a.rs\n    lib.rs\n"
+        );
+        let project = generic.build().unwrap();
+        let lib = std::fs::read_to_string(project.path.join("src/lib.rs")).unwrap();
+        assert_eq!(lib, "// lib\nmod a;\n");
+        assert!(project
+            .cargo(["check", "--offline"])
+            .unwrap()
+            .status
+            .success());
+        project.clear();
+    }
+
+    #[test]
+    fn counts_cover_nested_dirs_and_files() {
+        let builder = GenericBuilder::new("unbuilt");
+        assert!(builder.is_empty());
+        assert_eq!((builder.dir_count(), builder.file_count()), (1, 0));
+
+        let builder = builder
+            .add_dir(
+                Dir::new("unbuilt/src")
+                    .add_file("lib.rs", b"")
+                    .dir("bin", |bin| bin.add_file("a.rs", b"").add_file("b.rs", b"")),
+            )
+            .add_dir(Dir::new("unbuilt/src").add_file("extra.rs", b""))
+            .add_dir(Dir::new("unbuilt/empty"));
+        assert!(!builder.is_empty());
+        assert_eq!(builder.dir_count(), 4);
+        assert_eq!(builder.file_count(), 4);
+        assert!(!GenericBuilder::new("unbuilt")
+            .add_dir(Dir::new("unbuilt/empty"))
+            .is_empty());
+    }
+
+    #[test]
+    fn inspect_sees_the_project_mid_chain() {
+        let mut seen = vec![];
+        let builder = GenericBuilder::new("unbuilt")
+            .add_dir(Dir::new("unbuilt/src").add_file("lib.rs", b""))
+            .inspect(|project| seen.push(project.to_string()))
+            .add_dir(Dir::new("unbuilt/tests").add_file("a.rs", b""))
+            .inspect(|project| seen.push(project.to_string()));
+
+        assert_eq!(
+            seen,
+            [
+                "Project(unbuilt): 1 files in 2 dirs",
+                "Project(unbuilt): 2 files in 3 dirs"
+            ]
+        );
+        assert_eq!(builder.project().plan().len(), 5);
+    }
+
+    #[test]
+    fn add_bin_src_creates_discoverable_binaries() {
+        let project = RustBuilder::new("tmp_bin_src")
+            .add_cargo_toml(Manifest::try_from("bins", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .add_bin_src("first", None)
+            .add_bin_src("second", Some("fn main() { println!(\"2\"); }\n"))
+            .build()
+            .unwrap();
+
+        let bin = project.path.join("src/bin");
+        assert_eq!(
+            std::fs::read_to_string(bin.join("first.rs")).unwrap(),
+            "fn main() {}\n"
+        );
+        assert!(bin.join("second.rs").is_file());
+        let manifest = std::fs::read_to_string(project.path.join("Cargo.toml")).unwrap();
+        assert!(!manifest.contains("[[bin]]"));
+
+        let output = project
+            .cargo([
+                "metadata",
+                "--offline",
+                "--no-deps",
+                "--format-version",
+                "1",
+            ])
+            .unwrap();
+        let metadata = String::from_utf8(output.stdout).unwrap();
+        assert!(metadata.contains("\"name\":\"first\""));
+        assert!(metadata.contains("\"name\":\"second\""));
+        project.clear();
+    }
+
+    #[test]
+    fn test_target_adds_test_table_and_stub() {
+        let project = RustBuilder::new("tmp_test_target")
+            .add_cargo_toml(Manifest::try_from("tested", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .add_dir(Dir::new("tmp_test_target/src").add_file("lib.rs", b""))
+            .add_dir(
+                Dir::new("tmp_test_target/tests").add_file("existing.rs", b"#[test]\nfn ok() {}\n"),
+            )
+            .test_target("custom", "tests/custom/main.rs", false)
+            .test_target("existing", "tests/existing.rs", true)
+            .build()
+            .unwrap();
+
+        let read = |rel: &str| std::fs::read_to_string(project.path.join(rel)).unwrap();
+        assert_eq!(read("tests/custom/main.rs"), "fn main() {}\n");
+        assert_eq!(read("tests/existing.rs"), "#[test]\nfn ok() {}\n");
+        let manifest = read("Cargo.toml");
+        assert!(manifest.contains(
+            "[[test]]\nname = \"custom\"\npath = \"tests/custom/main.rs\"\nharness = false\n"
+        ));
+        assert!(manifest
+            .contains("name = \"existing\"\npath = \"tests/existing.rs\"\nharness = true\n"));
+        let parsed = Manifest::from_toml_str(&manifest).unwrap();
+        assert_eq!(parsed.tests.len(), 2);
+        assert_eq!(parsed.tests[0].harness, Some(false));
+
+        let output = project.cargo_offline(["test", "--no-run"]).unwrap();
+        assert!(output.status.success());
+        project.clear();
+    }
+
+    #[test]
+    fn add_bench_creates_file_and_bench_table() {
+        let project = RustBuilder::new("tmp_bench")
+            .add_cargo_toml(Manifest::try_from("bench", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .add_dir(Dir::new("tmp_bench/src").add_file("lib.rs", b""))
+            .add_bench("custom", None, Some(false))
+            .add_bench("plain", Some("// plain\n"), None)
+            .build()
+            .unwrap();
+
+        let read = |rel: &str| std::fs::read_to_string(project.path.join(rel)).unwrap();
+        assert_eq!(read("benches/custom.rs"), "fn main() {}\n");
+        assert_eq!(read("benches/plain.rs"), "// plain\n");
+        let manifest = read("Cargo.toml");
+        assert!(manifest.contains("[[bench]]\nname = \"custom\"\nharness = false\n"));
+        assert!(!manifest.contains("plain"));
+
+        let output = project.cargo(["bench", "--offline", "--no-run"]).unwrap();
+        assert!(output.status.success());
+        project.clear();
+    }
+
+    #[test]
+    fn root_config_files_are_created() {
+        let project = RustBuilder::new("tmp_root_files")
+            .root_file(".editorconfig", "root = true\n")
+            .rustfmt_config("max_width = 80\n")
+            .unwrap()
+            .clippy_config("msrv = \"1.31\"\n")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let read = |rel: &str| std::fs::read_to_string(project.path.join(rel)).unwrap();
+        assert_eq!(read(".editorconfig"), "root = true\n");
+        assert_eq!(read("rustfmt.toml"), "max_width = 80\n");
+        assert_eq!(read(".clippy.toml"), "msrv = \"1.31\"\n");
+        project.clear();
+
+        assert!(RustBuilder::new("tmp_root_files")
+            .rustfmt_config("max_width = ")
+            .is_err());
+    }
+
+    #[test]
+    fn registry_dependencies_and_config() {
+        let mut manifest = Manifest::try_from("registry", "0.1.0", &[], None, None).unwrap();
+        manifest.dependencies_mut().insert(
+            "private".to_owned(),
+            Dependency {
+                version: Some(semver::Version::parse("1.0.0").unwrap()),
+                registry: Some("my-registry".to_owned()),
+                ..Dependency::default()
+            },
+        );
+
+        let project = RustBuilder::new("tmp_registry")
+            .add_cargo_toml(manifest)
+            .unwrap()
+            .registry("my-registry", "https://example.com/index")
+            .unwrap()
+            .registry("other", "sparse+https://example.com/other/")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let read = |rel: &str| std::fs::read_to_string(project.path.join(rel)).unwrap();
+        assert!(read("Cargo.toml")
+            .contains("[dependencies.private]\nversion = \"1.0.0\"\nregistry = \"my-registry\"\n"));
+        assert_eq!(
+            read(".cargo/config.toml"),
+            "[registries.my-registry]\nindex = \"https://example.com/index\"\n\
+             [registries.other]\nindex = \"sparse+https://example.com/other/\"\n"
+        );
+        let mut parsed = Manifest::from_path(project.path.join("Cargo.toml")).unwrap();
+        assert_eq!(
+            parsed.dependencies_mut()["private"].registry.as_deref(),
+            Some("my-registry")
+        );
+        project.clear();
+    }
+
+    #[test]
+    fn scratch_registry_builds_stubbed_dependencies_offline() {
+        let mut manifest = Manifest::try_from("hermetic", "0.1.0", &[], None, None).unwrap();
+        manifest.add_dependency("stubbed", "1.2.3").unwrap();
+        manifest
+            .add_build_dependency("local", Dependency::path("local"))
+            .unwrap();
+
+        let project = RustBuilder::new("tmp_scratch_registry")
+            .add_cargo_toml(manifest)
+            .unwrap()
+            .add_dir(
+                Dir::new("tmp_scratch_registry/src").add_file("lib.rs", b"extern crate stubbed;"),
+            )
+            .add_dir(
+                Dir::new("tmp_scratch_registry/local")
+                    .add_file(
+                        "Cargo.toml",
+                        b"[package]\nname = \"local\"\nversion = \"0.1.0\"\n",
+                    )
+                    .dir("src", |src| src.add_file("lib.rs", b"")),
+            )
+            .scratch_registry()
+            .build()
+            .unwrap();
+
+        assert!(project.is_absent(".scratch-registry/local-0.1.0"));
+        let stub = Manifest::from_path(
+            project
+                .path
+                .join(".scratch-registry/stubbed-1.2.3/Cargo.toml"),
+        )
+        .unwrap();
+        assert_eq!(stub.package.name, "stubbed");
+        assert!(project
+            .file_contains(".cargo/config.toml", "replace-with = \".scratch-registry\"")
+            .unwrap());
+        let output = project.cargo_offline(["build"]).unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(project
+            .file_contains("Cargo.lock", "name = \"stubbed\"\nversion = \"1.2.3\"")
+            .unwrap());
+        project.clear();
+    }
+
+    #[test]
+    fn build_dependency_is_written_to_build_dependencies() {
+        let toml = RustBuilder::new("unbuilt")
+            .add_cargo_toml(Manifest::try_from("scripted", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .build_dependency("cc", "1.0.0")
+            .unwrap()
+            .build_dependency("helper", Dependency::path("../helper"))
+            .unwrap()
+            .manifest_toml()
+            .unwrap();
+        assert!(toml.ends_with(
+            "[build-dependencies]\ncc = \"1.0.0\"\n\n\
+             [build-dependencies.helper]\npath = \"../helper\"\n"
+        ));
+
+        let parsed = Manifest::from_toml_str(&toml).unwrap();
+        assert_eq!(rust_tools::to_toml(&parsed).unwrap(), toml);
+        assert!(RustBuilder::new("unbuilt")
+            .build_dependency("cc", "1.x")
+            .is_err());
+    }
+
+    #[test]
+    fn generate_lock_offline_writes_a_lockfile_cargo_accepts() {
+        let project = RustBuilder::new("tmp_offline_lock")
+            .add_cargo_toml(Manifest::try_from("locked", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .add_dir(Dir::new("tmp_offline_lock/src").add_file("lib.rs", b""))
+            .generate_lock_offline()
+            .build()
+            .unwrap();
+
+        let lock = std::fs::read_to_string(project.path.join("Cargo.lock")).unwrap();
+        assert!(lock.contains("[[package]]\nname = \"locked\"\nversion = \"0.1.0\"\n"));
+        let output = project
+            .cargo(["metadata", "--offline", "--locked"])
+            .unwrap();
+        assert!(output.status.success());
+        project.clear();
+    }
+
+    #[test]
+    fn workspace_path_joins_a_workspace_from_outside() {
+        let member = |name: &str| Manifest::try_from(name, "0.1.0", &[], None, None).unwrap();
+        let workspace = WorkspaceBuilder::new("tmp_workspace_path/root")
+            .member("../outside", |m| m)
+            .build()
+            .unwrap();
+        let outside = RustBuilder::new("tmp_workspace_path/outside")
+            .add_cargo_toml(member("outside"))
+            .unwrap()
+            .workspace_path("../root")
+            .add_dir(Dir::new("tmp_workspace_path/outside/src").add_file("lib.rs", b""))
+            .build()
+            .unwrap();
+
+        let manifest = std::fs::read_to_string(outside.path.join("Cargo.toml")).unwrap();
+        let package = manifest.split("\n[").next().unwrap();
+        assert!(package.contains("workspace = \"../root\"\n"));
+
+        let output = outside
+            .cargo([
+                "metadata",
+                "--offline",
+                "--no-deps",
+                "--format-version",
+                "1",
+            ])
+            .unwrap();
+        assert!(output.status.success());
+        let metadata = String::from_utf8(output.stdout).unwrap();
+        assert!(metadata.contains("tmp_workspace_path/root\""));
+        workspace.clear();
+        Project::new("tmp_workspace_path").clear();
+    }
+
+    #[test]
+    fn dependency_style_changes_manifest_toml() {
+        let builder = RustBuilder::new("unbuilt")
+            .add_cargo_toml(Manifest::try_from("styled", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .build_dependency("helper", Dependency::path("../helper"))
+            .unwrap();
+
+        let table = builder.manifest_toml().unwrap();
+        assert!(table.ends_with("\n[build-dependencies.helper]\npath = \"../helper\"\n"));
+        let inline = builder
+            .dependency_style(DependencyStyle::Inline)
+            .manifest_toml()
+            .unwrap();
+        assert!(inline.ends_with("\n[build-dependencies]\nhelper = { path = \"../helper\" }\n"));
+    }
+
+    #[test]
+    fn links_is_written_under_package() {
+        let builder = RustBuilder::new("tmp_links")
+            .add_cargo_toml(Manifest::try_from("native", "0.1.0", &[], None, None).unwrap())
+            .unwrap();
+        assert!(!builder.manifest_toml().unwrap().contains("links"));
+
+        let toml = builder.links("foo").manifest_toml().unwrap();
+        let package = toml.split("\n[").next().unwrap();
+        assert!(package.contains("links = \"foo\"\n"));
+    }
+
+    #[test]
+    fn map_contents_transforms_files_before_writing() {
+        let project = RustBuilder::new("tmp_map_contents")
+            .add_cargo_toml(Manifest::try_from("mapped", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .add_dir(
+                Dir::new("tmp_map_contents/src")
+                    .add_file("lib.rs", b"pub fn f() {}\n")
+                    .add_file_reader("notes.txt", &b"notes\n"[..]),
+            )
+            .map_contents(|path, bytes| {
+                if path.extension().is_some_and(|ext| ext == "rs") {
+                    [&b"// @generated\n"[..], bytes].concat()
+                } else {
+                    bytes.to_vec()
+                }
+            })
+            .map_contents(|_, bytes| bytes.to_ascii_uppercase())
+            .build()
+            .unwrap();
+
+        let read = |rel: &str| std::fs::read_to_string(project.path.join(rel)).unwrap();
+        assert_eq!(read("src/lib.rs"), "// @GENERATED\nPUB FN F() {}\n");
+        assert_eq!(read("src/notes.txt"), "NOTES\n");
+        assert!(read("Cargo.toml").starts_with("[PACKAGE]"));
+        project.clear();
+    }
+
+    #[test]
+    #[should_panic(expected = "refusing to use")]
+    fn empty_path_is_rejected() {
+        RustBuilder::new("");
+    }
+
+    #[test]
+    #[should_panic(expected = "refusing to use")]
+    fn current_dir_path_is_rejected() {
+        GenericBuilder::new(".");
+    }
+
+    #[test]
+    #[should_panic(expected = "refusing to use")]
+    fn filesystem_root_path_is_rejected() {
+        RustBuilder::new("/");
+    }
+
+    #[test]
+    #[should_panic(expected = "refusing to use")]
+    fn parent_dir_path_is_rejected() {
+        RustBuilder::with_dir_name("tmp_parent", "..");
+    }
+
+    #[test]
+    fn project_empty_build_creates_dir() {
+        let path = PathBuf::from("tmp");
+        let project = GenericBuilder::new(&path).build().unwrap();
+        assert!(path.exists());
+        project.clear();
+    }
+
+    #[test]
+    fn project_with_dir_and_files_works() {
+        let path = PathBuf::from("tmp2");
+        let project = GenericBuilder::new(&path)
+            .add_dir(Dir::new("tmp2/foo").add_file("bar", &[101u8]))
+            .build()
+            .unwrap();
+
+        assert!(path.exists());
+        let path = path.join("foo");
+        assert!(path.exists());
+        let path = path.join("bar");
+        assert!(path.exists());
+        project.clear();
+    }
+
+    #[test]
+    fn nested_dirs_are_created() {
+        let path = PathBuf::from("tmp4");
+        let project = GenericBuilder::new(&path)
+            .add_dir(Dir::new("tmp4").dir("src", |src| {
+                src.add_file("lib.rs", b"")
+                    .dir("bin", |bin| bin.add_file("main.rs", b"fn main() {}"))
+            }))
+            .build()
+            .unwrap();
+
+        assert!(path.join("src/lib.rs").exists());
+        assert!(path.join("src/bin/main.rs").exists());
+        project.clear();
+    }
+
+    #[test]
+    fn types_are_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<Project>();
+        assert_send_sync::<Dir>();
+        assert_send_sync::<File>();
+        assert_send_sync::<GenericBuilder>();
+        assert_send_sync::<RustBuilder>();
+        assert_send_sync::<EphemeralError>();
+    }
+
+    #[test]
+    fn build_many_builds_all_projects() {
+        let builders = (0..4)
+            .map(|i| {
+                let path = format!("tmp_many{}", i);
+                GenericBuilder::new(&path).add_dir(Dir::new(&path).add_file("foo", b"bar"))
+            })
+            .collect();
+
+        for project in build_many(builders) {
+            let project = project.unwrap();
+            assert!(project.path.join("foo").exists());
+            project.clear();
+        }
+    }
+
+    #[test]
+    fn on_workers_keeps_the_order_with_more_builders_than_workers() {
+        let paths: Vec<_> = (0..9).map(|i| format!("tmp_workers{}", i)).collect();
+        let builders = paths
+            .iter()
+            .map(|path| GenericBuilder::new(path).add_dir(Dir::new(path).add_file("foo", b"")))
+            .collect();
+
+        let projects = on_workers(builders, 2, Builder::build);
+        assert_eq!(projects.len(), paths.len());
+        for (project, path) in projects.into_iter().zip(paths.iter()) {
+            let project = project.unwrap();
+            assert_eq!(project.path, PathBuf::from(path));
+            assert!(project.path.join("foo").exists());
+            project.clear();
+        }
+        assert!(on_workers(Vec::<GenericBuilder>::new(), 2, Builder::build).is_empty());
+    }
+
+    #[test]
+    fn build_each_builds_every_case_in_its_own_dir() {
+        let cases = vec![("one", 1u8), ("two", 2), ("three", 3)];
+        let projects: Vec<_> = build_each(cases, |&(name, byte)| {
+            RustBuilder::new("tmp_each")
+                .add_cargo_toml(Manifest::try_from(name, "0.1.0", &[], None, None).unwrap())
+                .unwrap()
+                .add_dir(Dir::new("tmp_each/src").add_file("data", &[byte]))
+        })
+        .into_iter()
+        .map(Result::unwrap)
+        .collect();
+
+        let roots: BTreeSet<_> = projects
+            .iter()
+            .map(|project| project.path.clone())
+            .collect();
+        assert_eq!(roots.len(), 3);
+        let prefix = format!("tmp_each-{}-", process::id());
+        for (project, name) in projects.iter().zip(&["one", "two", "three"]) {
+            assert!(project.path.starts_with(env::temp_dir()));
+            let dir_name = project.path.file_name().unwrap().to_string_lossy();
+            assert!(dir_name.starts_with(&prefix));
+            assert!(project
+                .file_contains("Cargo.toml", &format!("name = \"{}\"", name))
+                .unwrap());
+            assert!(project.path.join("src/data").exists());
+        }
+        assert!(!Path::new("tmp_each").exists());
+        for project in projects {
+            project.clear();
+        }
+    }
+
+    #[test]
+    fn build_each_refuses_dirs_left_by_earlier_runs() {
+        // Taken before the counter moves on, so one of these is the dir build_each picks.
+        let next = NEXT_DIR.load(Ordering::Relaxed);
+        let stale: Vec<_> = (next..next + 32)
+            .map(|n| env::temp_dir().join(format!("tmp_each_stale-{}-{}", process::id(), n)))
+            .collect();
+        for dir in stale.iter() {
+            fs::create_dir_all(dir.join("leftover")).unwrap();
+        }
+
+        let result = build_each(vec![()], |_| RustBuilder::new("tmp_each_stale")).remove(0);
+        for dir in stale.iter() {
+            fs::remove_dir_all(dir).unwrap();
+        }
+        match result {
+            Err(EphemeralError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::AlreadyExists),
+            other => panic!("expected AlreadyExists, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn project_is_borrowed_not_cloned() {
+        let mut builder =
+            GenericBuilder::new("tmp3").add_dir(Dir::new("tmp3/foo").add_file("bar", &[0u8; 1024]));
+
+        assert!(std::ptr::eq(builder.project(), &builder.project));
+        builder.project_mut().dirs.push(Dir::new("tmp3/baz"));
+        assert_eq!(builder.project().dirs.len(), 3);
+        assert_eq!(builder.project.dirs[2].path, PathBuf::from("tmp3/baz"));
+    }
+}
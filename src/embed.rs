@@ -0,0 +1,41 @@
+//! Fixture dirs compiled into the test binary with `embed_dir!`, for `Dir::from_embedded()`.
+//! Walking a dir at compile time needs a proc macro, so the files to embed are listed explicitly.
+
+/// A dir embedded with `embed_dir!`. `path` is relative to the crate that embedded it.
+#[derive(Clone, Copy, Debug)]
+pub struct EmbeddedDir {
+    pub path: &'static str,
+    pub files: &'static [EmbeddedFile],
+}
+
+/// A file of an `EmbeddedDir`, with its path relative to the dir.
+#[derive(Clone, Copy, Debug)]
+pub struct EmbeddedFile {
+    pub path: &'static str,
+    pub contents: &'static [u8],
+}
+
+/// Embeds the listed files of a dir, given relative to the calling crate's `Cargo.toml`, into the
+/// binary as an `EmbeddedDir`:
+///
+/// ```rust
+/// use ephemeral::{embed::EmbeddedDir, embed_dir};
+///
+/// static FIXTURE: EmbeddedDir = embed_dir!("src", ["lib.rs", "embed.rs"]);
+/// assert_eq!(FIXTURE.files.len(), 2);
+/// ```
+///
+/// Files may be in subdirs, e.g. `"src/main.rs"`. A listed file that doesn't exist fails the
+/// build.
+#[macro_export]
+macro_rules! embed_dir {
+    ($dir:literal, [$($file:literal),* $(,)?]) => {
+        $crate::embed::EmbeddedDir {
+            path: $dir,
+            files: &[$($crate::embed::EmbeddedFile {
+                path: $file,
+                contents: include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $dir, "/", $file)),
+            }),*],
+        }
+    };
+}
@@ -0,0 +1,166 @@
+use std::{
+    convert::Infallible, error::Error, fmt, io, path::PathBuf, process::ExitStatus, time::Duration,
+};
+
+/// Errors returned while describing or creating a project.
+#[derive(Debug)]
+pub enum EphemeralError {
+    Io(io::Error),
+    Serialize(toml::ser::Error),
+    Deserialize(toml::de::Error),
+    Version(semver::SemVerError),
+    NotFound(PathBuf),
+    InvalidResolver(String),
+    InvalidEdition(String),
+    Timeout(Duration),
+    UnknownCrate(String),
+    DependencyCycle(Vec<String>),
+    CargoFailed {
+        status: ExitStatus,
+        stderr: String,
+    },
+    InvalidBase64(String),
+    CheckFailed(String),
+    DuplicatePackageName(Vec<String>),
+    UnknownMember(String),
+    OutsideRoot(PathBuf),
+    WriteVerificationFailed {
+        path: PathBuf,
+        expected: u64,
+        actual: u64,
+    },
+    WriteCorrupted(PathBuf),
+    InvalidMetadata(String),
+    DuplicateFile(PathBuf),
+    CreateOrderCycle(PathBuf),
+}
+
+impl fmt::Display for EphemeralError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EphemeralError::Io(ref err) => write!(f, "io error: {}", err),
+            EphemeralError::Serialize(ref err) => write!(f, "cannot serialize manifest: {}", err),
+            EphemeralError::Deserialize(ref err) => write!(f, "cannot parse manifest: {}", err),
+            EphemeralError::Version(ref err) => write!(f, "invalid version: {}", err),
+            EphemeralError::NotFound(ref path) => write!(f, "{} does not exist", path.display()),
+            EphemeralError::InvalidResolver(ref resolver) => {
+                write!(f, "unknown resolver version \"{}\"", resolver)
+            }
+            EphemeralError::InvalidEdition(ref edition) => {
+                write!(f, "unknown edition \"{}\"", edition)
+            }
+            EphemeralError::Timeout(ref timeout) => {
+                write!(f, "process did not finish within {:?}", timeout)
+            }
+            EphemeralError::UnknownCrate(ref name) => write!(f, "no crate named {}", name),
+            EphemeralError::DependencyCycle(ref crates) => {
+                write!(f, "dependency cycle: {}", crates.join(" -> "))
+            }
+            EphemeralError::CargoFailed {
+                ref status,
+                ref stderr,
+            } => write!(f, "cargo failed ({}):\n{}", status, stderr),
+            EphemeralError::InvalidBase64(ref encoded) => write!(f, "invalid base64: {}", encoded),
+            EphemeralError::CheckFailed(ref report) => write!(f, "cargo check failed\n{}", report),
+            EphemeralError::DuplicatePackageName(ref names) => {
+                write!(
+                    f,
+                    "package names used by several members: {}",
+                    names.join(", ")
+                )
+            }
+            EphemeralError::UnknownMember(ref name) => {
+                write!(f, "{} is not a workspace member", name)
+            }
+            EphemeralError::OutsideRoot(ref path) => {
+                write!(f, "{} is outside the project root", path.display())
+            }
+            EphemeralError::WriteVerificationFailed {
+                ref path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} is {} bytes on disk, expected {}",
+                path.display(),
+                actual,
+                expected
+            ),
+            EphemeralError::WriteCorrupted(ref path) => write!(
+                f,
+                "{} on disk differs from what was written",
+                path.display()
+            ),
+            EphemeralError::InvalidMetadata(ref reason) => {
+                write!(f, "invalid package metadata: {}", reason)
+            }
+            EphemeralError::DuplicateFile(ref path) => {
+                write!(f, "{} was added more than once", path.display())
+            }
+            EphemeralError::CreateOrderCycle(ref path) => write!(
+                f,
+                "the creation order constraints on {} form a cycle",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Error for EphemeralError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            EphemeralError::Io(ref err) => Some(err),
+            EphemeralError::Serialize(ref err) => Some(err),
+            EphemeralError::Deserialize(ref err) => Some(err),
+            EphemeralError::Version(ref err) => Some(err),
+            EphemeralError::NotFound(_)
+            | EphemeralError::InvalidResolver(_)
+            | EphemeralError::InvalidEdition(_)
+            | EphemeralError::Timeout(_)
+            | EphemeralError::UnknownCrate(_)
+            | EphemeralError::DependencyCycle(_)
+            | EphemeralError::CargoFailed { .. }
+            | EphemeralError::InvalidBase64(_)
+            | EphemeralError::CheckFailed(_)
+            | EphemeralError::DuplicatePackageName(_)
+            | EphemeralError::UnknownMember(_)
+            | EphemeralError::OutsideRoot(_)
+            | EphemeralError::WriteVerificationFailed { .. }
+            | EphemeralError::WriteCorrupted(_)
+            | EphemeralError::InvalidMetadata(_)
+            | EphemeralError::DuplicateFile(_)
+            | EphemeralError::CreateOrderCycle(_) => None,
+        }
+    }
+}
+
+/// Lets APIs taking `TryInto` inputs also accept types that convert infallibly.
+impl From<Infallible> for EphemeralError {
+    fn from(err: Infallible) -> Self {
+        match err {}
+    }
+}
+
+impl From<io::Error> for EphemeralError {
+    fn from(err: io::Error) -> Self {
+        EphemeralError::Io(err)
+    }
+}
+
+impl From<toml::ser::Error> for EphemeralError {
+    fn from(err: toml::ser::Error) -> Self {
+        EphemeralError::Serialize(err)
+    }
+}
+
+impl From<toml::de::Error> for EphemeralError {
+    fn from(err: toml::de::Error) -> Self {
+        EphemeralError::Deserialize(err)
+    }
+}
+
+impl From<semver::SemVerError> for EphemeralError {
+    fn from(err: semver::SemVerError) -> Self {
+        EphemeralError::Version(err)
+    }
+}
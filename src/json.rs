@@ -0,0 +1,71 @@
+//! Renders values as JSON for `Dir::add_json_file()`, without a JSON dependency: values are
+//! first converted to a `toml::Value` and then written out.
+
+use std::fmt::Write;
+
+use crate::error::EphemeralError;
+
+/// Serializes `value` as JSON indented by two spaces, ending with a newline.
+pub(crate) fn to_json<T: serde::Serialize>(value: &T) -> Result<String, EphemeralError> {
+    let mut json = String::new();
+    write_value(&mut json, &toml::Value::try_from(value)?, 0)?;
+    json.push('\n');
+
+    Ok(json)
+}
+
+fn write_value(json: &mut String, value: &toml::Value, depth: usize) -> Result<(), EphemeralError> {
+    let indent = |json: &mut String, depth: usize| json.push_str(&"  ".repeat(depth));
+    match *value {
+        toml::Value::String(ref s) => write_string(json, s),
+        toml::Value::Integer(n) => json.push_str(&n.to_string()),
+        toml::Value::Float(n) if n.is_finite() => json.push_str(&n.to_string()),
+        toml::Value::Float(_) => return Err(toml::ser::Error::NumberInvalid.into()),
+        toml::Value::Boolean(b) => json.push_str(if b { "true" } else { "false" }),
+        toml::Value::Datetime(ref datetime) => write_string(json, &datetime.to_string()),
+        toml::Value::Array(ref values) if values.is_empty() => json.push_str("[]"),
+        toml::Value::Array(ref values) => {
+            json.push_str("[\n");
+            for (i, value) in values.iter().enumerate() {
+                indent(json, depth + 1);
+                write_value(json, value, depth + 1)?;
+                json.push_str(if i + 1 < values.len() { ",\n" } else { "\n" });
+            }
+            indent(json, depth);
+            json.push(']');
+        }
+        toml::Value::Table(ref table) if table.is_empty() => json.push_str("{}"),
+        toml::Value::Table(ref table) => {
+            json.push_str("{\n");
+            for (i, (key, value)) in table.iter().enumerate() {
+                indent(json, depth + 1);
+                write_string(json, key);
+                json.push_str(": ");
+                write_value(json, value, depth + 1)?;
+                json.push_str(if i + 1 < table.len() { ",\n" } else { "\n" });
+            }
+            indent(json, depth);
+            json.push('}');
+        }
+    }
+
+    Ok(())
+}
+
+fn write_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => {
+                write!(json, "\\u{:04x}", c as u32).expect("writing to a String can't fail")
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
@@ -33,11 +33,42 @@
 //! This will create a new project in a dir called `tmp` which will contain a dir "foo" which will
 //! contain a file `bar` with `e` (101u8) written to the file.
 
-use std::fs::{create_dir_all, remove_dir_all};
-use std::{error::Error, path::PathBuf};
+use std::borrow::Cow;
+use std::collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet};
+use std::fs::{self, create_dir_all, remove_dir_all, File as FsFile};
+use std::hash::Hasher;
+use std::io::{self, BufWriter, Read, Write};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    fmt, iter,
+    path::{Component, Path, PathBuf},
+    thread,
+};
+
+use crate::{
+    builder::{GenericBuilder, RustBuilder},
+    error::EphemeralError,
+    rust_tools::Manifest,
+};
 
 pub mod builder;
+#[cfg(feature = "embed")]
+pub mod embed;
+pub mod error;
+#[cfg(feature = "hash")]
+mod hash;
+#[cfg(feature = "json")]
+mod json;
 pub mod rust_tools;
+pub mod spec;
+#[cfg(feature = "tar")]
+mod tar;
+#[cfg(feature = "zip")]
+mod zip;
 
 /// Project represents a project created on the file system at any user-defined location defined by
 /// the path parameter to the `new()` function.
@@ -45,107 +76,3325 @@ pub mod rust_tools;
 /// This struct as a builder so directories and files can be added to it. Remember to call `build()`
 /// at the end to create the project in the filesystem. The dirs vector will contain all the dirs &
 /// subdirs in the project, which are added when the directory is added to the project.
-
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct Project {
     pub path: PathBuf,
     dirs: Vec<Dir>,
+    map: Option<ContentMap>,
+    clear_on_drop: bool,
+    cargo_bin: Option<PathBuf>,
+    verify_writes: bool,
+    cargo_flags: Vec<String>,
+    strict_paths: bool,
+    write_buffer_size: usize,
+    incremental: bool,
+    duplicate_dirs: DuplicateDirs,
+    /// Missing ancestors of the root created by `build()`, innermost first.
+    created_parents: Vec<PathBuf>,
+    /// `(after, before)` pairs from `Builder::create_after()`.
+    create_order: Vec<(PathBuf, PathBuf)>,
+    default_mode: Option<u32>,
+    /// Set only on the project `build()` returns when `clear_on_drop` is on.
+    clear_guard: Option<ClearGuard>,
+}
+
+/// Makes the project holding it delete itself when dropped. It can't be cloned, so clones of a
+/// project and the builders holding one never delete anything.
+#[derive(Debug)]
+struct ClearGuard {
+    persist_on_panic: bool,
 }
 
 impl Project {
     /// Creates a new Project at the specified `path`. This will automatically add a "root" directory
     /// to the `dirs` vector.
-
+    ///
+    /// Panics if `path` is empty or names a directory like `.`, `..` or `/`, since `clear()` would
+    /// then delete far more than the project.
     pub fn new<T>(path: T) -> Project
     where
         T: Into<PathBuf> + Clone,
     {
+        Project::with_capacity(path, 0)
+    }
+
+    /// Wraps the existing directory at `path` in a Project, e.g. one created by an earlier tool
+    /// step, to use helpers like `cargo()` on it. Nothing is created or read; the project only
+    /// holds the root dir, so `tree()` doesn't list what is on disk.
+    ///
+    /// `clear()` deletes everything under `path`, not just files added through this crate, so be
+    /// sure of the path. Panics like `new()` on paths such as `.` or `/`.
+    pub fn open<P: Into<PathBuf>>(path: P) -> Project {
+        Project::with_capacity(path, 0)
+    }
+
+    /// Like `new()`, but reserves room for `dirs` more dirs besides the root one.
+    pub(crate) fn with_capacity<T: Into<PathBuf>>(path: T, dirs: usize) -> Project {
         let path = path.into();
-        Project {
-            dirs: vec![Dir::new(&path)],
+        assert!(
+            matches!(path.components().next_back(), Some(Component::Normal(_))),
+            "refusing to use {:?} as a project root",
+            path
+        );
+        let mut project = Project {
+            dirs: Vec::with_capacity(dirs + 1),
             path,
+            map: None,
+            clear_on_drop: false,
+            cargo_bin: None,
+            verify_writes: false,
+            cargo_flags: vec![],
+            strict_paths: false,
+            write_buffer_size: DEFAULT_WRITE_BUFFER_SIZE,
+            incremental: false,
+            duplicate_dirs: DuplicateDirs::default(),
+            created_parents: vec![],
+            create_order: vec![],
+            default_mode: None,
+            clear_guard: None,
+        };
+        project.dirs.push(Dir::new(&project.path));
+
+        project
+    }
+
+    /// Creates every dir of the project on the filesystem, passing file contents through the
+    /// transform set by `Builder::map_contents`, if any.
+    pub(crate) fn create_dirs(&mut self) -> Result<(), EphemeralError> {
+        if self.strict_paths {
+            self.validate()?;
+        }
+        self.created_parents.extend(missing_parents(&self.path));
+        let dirs = self.resolved_dirs()?;
+        let options = WriteOptions {
+            map: self.map.as_ref(),
+            verify: self.verify_writes,
+            buffer_size: self.write_buffer_size,
+            incremental: self.incremental,
+            default_mode: self.default_mode,
+        };
+        if self.create_order.is_empty() {
+            for dir in dirs.iter() {
+                dir.create(&options)?;
+            }
+            return Ok(());
+        }
+
+        let steps = order_steps(&steps(&dirs), &self.create_order)?;
+        for step in steps.iter() {
+            match *step {
+                Step::Dir(dir) => dir.path.mkdir_p()?,
+                Step::File(file, dir) => file.create(&dir.path, &options)?,
+            }
+        }
+        // Dirs are made read-only last, children first, so nothing has to be written into them.
+        for step in steps.iter().rev() {
+            if let Step::Dir(dir) = *step {
+                if dir.readonly {
+                    set_readonly(&dir.path)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that every dir and file of the project is under its root, returning
+    /// `EphemeralError::OutsideRoot` for the first one that isn't, e.g. an absolute path added
+    /// with `add_file()`. Such paths would be written outside the project and left behind by
+    /// `clear()`. Paths going through `..` count as outside.
+    pub fn validate(&self) -> Result<(), EphemeralError> {
+        let outside = |path: &Path| {
+            !path.starts_with(&self.path) || path.components().any(|c| c == Component::ParentDir)
+        };
+        for dir in self.all_dirs() {
+            let mut paths = iter::once(&dir.path).chain(dir.files.iter().map(|file| &file.path));
+            if let Some(path) = paths.find(|path| outside(path)) {
+                return Err(EphemeralError::OutsideRoot(path.clone()));
+            }
         }
+
+        Ok(())
+    }
+
+    pub(crate) fn set_write_buffer_size(&mut self, size: usize) {
+        self.write_buffer_size = size;
+    }
+
+    pub(crate) fn set_strict_paths(&mut self, yes: bool) {
+        self.strict_paths = yes;
+    }
+
+    /// Chains `f` after the current content transform.
+    pub(crate) fn map_contents<F>(&mut self, f: F)
+    where
+        F: Fn(&Path, &[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        self.map = Some(match self.map.take() {
+            Some(ContentMap(prev)) => {
+                ContentMap(Arc::new(move |path, bytes| f(path, &prev(path, bytes))))
+            }
+            None => ContentMap(Arc::new(f)),
+        });
     }
 
     /// Deletes the project from the filesystem. This function can be used to clear the project
-    /// after running the tests.
+    /// after running the tests. The root is always `path`, even if no dirs were added.
+    ///
+    /// Parents of the root that `build()` had to create, e.g. `a/b` for a project at `a/b/c`, are
+    /// removed too, innermost first, as long as they are empty. The first one that isn't (say,
+    /// because another project shares it) is kept along with everything above it. Parents that
+    /// already existed are never touched.
     ///
     /// This function panics if a directory cannot be deleted.
+    pub fn clear(mut self) {
+        self.clear_guard = None;
+        self.remove().expect("can't delete directory");
+    }
 
-    pub fn clear(self) {
-        remove_dir_all(&self.dirs[0].path).expect("can't delete directory")
+    /// Sets whether the project `build()` returns deletes itself when dropped, like `clear()`
+    /// does. Off by default. Errors while deleting on drop are ignored.
+    pub(crate) fn set_clear_on_drop(&mut self, yes: bool) {
+        self.clear_on_drop = yes;
     }
-}
 
-/// Represents a dir in the filesystem. Accepts a path and contains a vector of files added.
-///
-/// To a Dir, you can attach files but not other dirs. To attach subdirectories, add them
-/// directly to Project and specify the parent dir in the path.
+    /// Gives the project a `ClearGuard` if `clear_on_drop` is set. Called only on projects just
+    /// written to disk for the caller, by `build()` and `duplicate()`.
+    pub(crate) fn guard_if_clearing(mut self) -> Project {
+        if self.clear_on_drop {
+            self.clear_guard = Some(ClearGuard {
+                persist_on_panic: false,
+            });
+        }
 
-#[derive(Clone, Debug)]
-pub struct Dir {
-    pub path: PathBuf,
-    files: Vec<File>,
-}
+        self
+    }
 
-impl Dir {
-    pub fn new<T: Into<PathBuf>>(path: T) -> Dir {
-        Dir {
-            path: path.into(),
-            files: vec![],
+    /// Removes the project's `ClearGuard`, if any, e.g. when it is handed back to a builder.
+    pub(crate) fn unguard(&mut self) {
+        self.clear_guard = None;
+    }
+
+    pub(crate) fn set_verify_writes(&mut self, yes: bool) {
+        self.verify_writes = yes;
+    }
+
+    pub(crate) fn set_incremental(&mut self, yes: bool) {
+        self.incremental = yes;
+    }
+
+    pub(crate) fn set_default_mode(&mut self, mode: u32) {
+        self.default_mode = Some(mode);
+    }
+
+    pub(crate) fn set_duplicate_dirs(&mut self, policy: DuplicateDirs) {
+        self.duplicate_dirs = policy;
+    }
+
+    /// Returns the top-level dirs `build()` creates under the `duplicate_dirs` policy: as added,
+    /// or combined with `merge_dirs()` under `DuplicateDirs::Merge`. Under `DuplicateDirs::Error`
+    /// this fails with `EphemeralError::DuplicateFile` if any file was added more than once.
+    pub(crate) fn resolved_dirs(&self) -> Result<Cow<'_, [Dir]>, EphemeralError> {
+        match self.duplicate_dirs {
+            DuplicateDirs::Append => Ok(Cow::Borrowed(&self.dirs)),
+            DuplicateDirs::Merge => Ok(Cow::Owned(merge_dirs(&self.dirs))),
+            DuplicateDirs::Error => {
+                self.check_duplicate_files()?;
+                Ok(Cow::Borrowed(&self.dirs))
+            }
         }
     }
 
-    /// Adds a file to the Dir. Accepts any type that can be converted to a PathBuf just like the
-    /// rest of the crate. Contents of the file should be specified as well (in bytes).
+    /// Returns `EphemeralError::DuplicateFile` for the first file added more than once.
+    fn check_duplicate_files(&self) -> Result<(), EphemeralError> {
+        let mut seen = BTreeSet::new();
+        for dir in self.all_dirs() {
+            if let Some(file) = dir.files.iter().find(|file| !seen.insert(&file.path)) {
+                return Err(EphemeralError::DuplicateFile(file.path.clone()));
+            }
+        }
 
-    pub fn add_file<T: Into<PathBuf>>(mut self, path: T, contents: &[u8]) -> Self {
-        let path = path.into();
-        let full_path = if path.is_relative() {
-            self.path.join(path)
-        } else {
-            path
-        };
+        Ok(())
+    }
 
-        self.files.push(File::new(full_path, contents));
+    /// Makes the project delete itself when dropped, unless it is dropped while the thread is
+    /// panicking, e.g. because an assertion in the test failed. The project is then kept on disk
+    /// for inspection. Panics are detected with `std::thread::panicking()`, so a project moved
+    /// out of the panicking thread (or leaked) before the panic is not kept.
+    pub fn persist_on_failure(mut self) -> Self {
+        self.clear_guard = Some(ClearGuard {
+            persist_on_panic: true,
+        });
 
         self
     }
-}
 
-impl AsMut<Dir> for Dir {
-    fn as_mut(&mut self) -> &mut Dir {
-        self
+    /// Whether dropping the project now should delete it.
+    fn clears_on_drop(&self, panicking: bool) -> bool {
+        self.clear_guard
+            .as_ref()
+            .is_some_and(|guard| !(panicking && guard.persist_on_panic))
     }
-}
 
-/// Represents a file stored in the filesystem. Contains the path and the contents in bytes.
+    fn remove(&self) -> io::Result<()> {
+        let removals = self.removals()?;
+        // Read-only dirs (and on Windows, files) can't have their entries deleted.
+        for (path, _) in removals.iter() {
+            let metadata = fs::symlink_metadata(path)?;
+            if !metadata.file_type().is_symlink() && metadata.permissions().readonly() {
+                make_writable(path, metadata.permissions())?;
+            }
+        }
+        for (path, is_dir) in removals.into_iter().rev() {
+            if is_dir {
+                fs::remove_dir(path)?;
+            } else {
+                fs::remove_file(path)?;
+            }
+        }
+        for parent in self.created_parents.iter() {
+            if fs::remove_dir(parent).is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
 
-#[derive(Clone, Debug)]
-pub struct File {
-    pub path: PathBuf,
-    contents: Vec<u8>,
-}
+    /// Returns every path `clear()` would delete, without deleting anything: the root followed by
+    /// everything under it, sorted. Symlinks are listed but not followed. Returns an empty list if
+    /// the root doesn't exist. Created parents of the root, which `clear()` removes if empty,
+    /// aren't listed.
+    pub fn clear_dry_run(&self) -> Vec<PathBuf> {
+        self.removals()
+            .map(|paths| paths.into_iter().map(|(path, _)| path).collect())
+            .unwrap_or_default()
+    }
 
-impl File {
-    pub fn new<T: Into<PathBuf>>(path: T, contents: &[u8]) -> File {
-        File {
-            path: path.into(),
-            contents: contents.into(),
+    /// Lists the paths `clear()` deletes and whether each is a dir, parents before children.
+    fn removals(&self) -> io::Result<Vec<(PathBuf, bool)>> {
+        if fs::symlink_metadata(&self.path)?.file_type().is_symlink() {
+            return Ok(vec![(self.path.clone(), false)]);
         }
+
+        let mut paths = vec![(self.path.clone(), true)];
+        paths.extend(
+            entries(&self.path)?
+                .into_iter()
+                .map(|(rel, is_dir)| (self.path.join(rel), is_dir)),
+        );
+
+        Ok(paths)
     }
-}
 
-/// Adds common path-based function. This allows a path-based type to create directories. mkdir_p
-/// will recursively create a directory and all of its parent components if they are missing while
-/// mkdir will create a single directory.
+    /// Turns the project back into a builder holding all of its dirs and files, so that it can be
+    /// modified and built again.
+    pub fn into_builder(self) -> GenericBuilder {
+        GenericBuilder::from_project(self)
+    }
 
-pub(crate) trait FilePath {
-    fn mkdir_p(&self) -> Result<(), Box<dyn Error>>;
+    /// Like `into_builder()`, but returns a `RustBuilder` with `manifest`. The project's existing
+    /// root `Cargo.toml` is replaced by one generated from `manifest` when it is built.
+    pub fn into_rust_builder(mut self, manifest: Manifest) -> Result<RustBuilder, EphemeralError> {
+        let cargo_toml = self.path.join("Cargo.toml");
+        self.root_mut().files.retain(|file| file.path != cargo_toml);
+
+        RustBuilder::from_project(self).add_cargo_toml(manifest)
+    }
+
+    /// Renders the dirs and files added to the project as an indented tree, one entry per line
+    /// sorted by path, with dirs marked by a trailing `/` and binary files by their size. Dirs
+    /// added more than once are shown as the `duplicate_dirs` policy creates them.
+    pub fn tree(&self) -> String {
+        let dirs = self.resolved_dirs().unwrap_or(Cow::Borrowed(&self.dirs));
+        let mut entries = BTreeMap::new();
+        for dir in flatten(&dirs) {
+            entries.insert(self.relative(&dir.path), "/".to_owned());
+            for file in dir.files.iter() {
+                let suffix = match file.binary_len() {
+                    Some(len) => format!(" (binary, {} bytes)", len),
+                    None => String::new(),
+                };
+                entries.insert(self.relative(&file.path), suffix);
+            }
+        }
+
+        let mut tree = format!("{}/\n", self.path.display());
+        for (path, suffix) in entries {
+            if path.as_os_str().is_empty() {
+                continue;
+            }
+            let depth = path.components().count();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            tree.push_str(&"  ".repeat(depth));
+            tree.push_str(&name);
+            tree.push_str(&suffix);
+            tree.push('\n');
+        }
+
+        tree
+    }
+
+    /// Returns the paths building the project creates, in the order they are created: each dir,
+    /// then its files, then its child dirs. Constraints from `Builder::create_after()` hold back
+    /// the entries they name, and whatever becomes ready first keeps this order among itself. If
+    /// the constraints can't be met, `build()` fails and this returns the order without them.
+    /// Dirs added more than once are planned as the `duplicate_dirs` policy creates them; if
+    /// `DuplicateDirs::Error` makes `build()` fail, every copy is listed.
+    pub fn plan(&self) -> Vec<PathBuf> {
+        let dirs = self.resolved_dirs().unwrap_or(Cow::Borrowed(&self.dirs));
+        let steps = steps(&dirs);
+        let ordered = order_steps(&steps, &self.create_order)
+            .unwrap_or_else(|_| steps.iter().map(|&(step, _)| step).collect());
+
+        ordered
+            .iter()
+            .map(|step| step.path().to_path_buf())
+            .collect()
+    }
+
+    pub(crate) fn create_after(&mut self, after: PathBuf, before: PathBuf) {
+        self.create_order.push((after, before));
+    }
+
+    /// Sorts the dirs, and the files and child dirs of every dir, by path.
+    pub(crate) fn sort_entries(&mut self) {
+        self.dirs.sort_by(|a, b| a.path.cmp(&b.path));
+        for dir in self.dirs.iter_mut() {
+            dir.sort_entries();
+        }
+    }
+
+    /// Moves the project to `path`, rewriting the paths of dirs and files under the old root.
+    pub(crate) fn move_root(&mut self, path: PathBuf) {
+        for dir in self.dirs.iter_mut() {
+            dir.move_root(&self.path, &path);
+        }
+        self.path = path;
+    }
+
+    /// Rewrites the paths of the dir or file at `from` and of everything under it to start with
+    /// `to` instead.
+    pub(crate) fn rename_path(&mut self, from: &Path, to: &Path) {
+        for dir in self.dirs.iter_mut() {
+            dir.move_root(from, to);
+        }
+    }
+
+    /// Counts the distinct paths of the dirs added to the project, nested ones and the root
+    /// included.
+    pub(crate) fn dir_count(&self) -> usize {
+        let dirs: BTreeSet<_> = self.all_dirs().into_iter().map(|dir| &dir.path).collect();
+        dirs.len()
+    }
+
+    /// Counts the files added to the project, in every dir.
+    pub(crate) fn file_count(&self) -> usize {
+        self.all_dirs().iter().map(|dir| dir.files.len()).sum()
+    }
+
+    /// Returns every dir added to the project, including nested ones, parents first.
+    pub(crate) fn all_dirs(&self) -> Vec<&Dir> {
+        flatten(&self.dirs)
+    }
+
+    fn relative(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(&self.path).unwrap_or(path).to_path_buf()
+    }
+
+    /// Returns the root dir of the project, adding it first if the project has no dirs.
+    pub(crate) fn root_mut(&mut self) -> &mut Dir {
+        if self.dirs.first().map_or(true, |dir| dir.path != self.path) {
+            self.dirs.insert(0, Dir::new(&self.path));
+        }
+
+        &mut self.dirs[0]
+    }
+
+    /// Reads the project's `Cargo.toml` from disk, lets `f` change it and writes it back, e.g. to
+    /// test how a tool reacts to a manifest edit. Tables that `Manifest` doesn't model, and any
+    /// comments or formatting, are lost in the round-trip.
+    pub fn modify_manifest<F>(&self, f: F) -> Result<(), EphemeralError>
+    where
+        F: FnOnce(&mut Manifest),
+    {
+        let path = self.path.join("Cargo.toml");
+        let mut manifest = Manifest::from_path(&path)?;
+        f(&mut manifest);
+
+        Ok(fs::write(path, rust_tools::to_toml(&manifest)?)?)
+    }
+
+    /// Returns the path of the root `Cargo.toml`, e.g. for a `--manifest-path` argument. The file
+    /// exists once a builder that writes a manifest has built the project.
+    pub fn manifest_path(&self) -> PathBuf {
+        self.path.join("Cargo.toml")
+    }
+
+    /// Returns the path of the root crate's `src` dir.
+    pub fn src_dir(&self) -> PathBuf {
+        self.path.join("src")
+    }
+
+    /// Returns the path of the `Cargo.toml` of the workspace member `name`, which
+    /// `WorkspaceBuilder::member()` puts in the `name` dir of the workspace.
+    pub fn member_manifest_path(&self, name: &str) -> PathBuf {
+        self.path.join(name).join("Cargo.toml")
+    }
+
+    /// Returns the dir `cargo build` puts the project's binaries in, `target/debug` under the root.
+    /// A target dir set with `CARGO_TARGET_DIR` or `--target-dir` is not taken into account.
+    pub fn bin_path(&self) -> PathBuf {
+        self.path.join("target").join("debug")
+    }
+
+    /// Prepends the absolute `bin_path()` to the `PATH` of `cmd`, so the project's binaries can be
+    /// run by name. A `PATH` already set on `cmd` is extended, otherwise the current one is.
+    /// Fails if the dir can't be put in `PATH`, e.g. because it contains the separator.
+    pub fn with_bin_on_path(&self, cmd: &mut Command) -> Result<(), EphemeralError> {
+        let bin = env::current_dir()?.join(self.bin_path());
+        let path = match cmd.get_envs().find(|&(key, _)| key == "PATH") {
+            Some((_, path)) => path.map(OsStr::to_os_string),
+            None => env::var_os("PATH"),
+        }
+        .unwrap_or_default();
+        let path = env::join_paths(iter::once(bin).chain(env::split_paths(&path)))
+            .map_err(io::Error::other)?;
+        cmd.env("PATH", path);
+
+        Ok(())
+    }
+
+    /// Returns the root path with all symlinks resolved, as the OS reports it (e.g. `/private/tmp`
+    /// rather than `/tmp` on macOS). Unlike `path`, this is always absolute, and it needs the
+    /// root to exist, so it can only be called after `build()`.
+    pub fn canonical_root(&self) -> Result<PathBuf, EphemeralError> {
+        Ok(self.path.canonicalize()?)
+    }
+
+    /// Calls `f` with the path relative to the root and the metadata of every entry under the
+    /// project root on disk, including files that weren't added through this crate, like
+    /// `target/`. Entries are visited depth first, each dir before its contents, and the entries of
+    /// a dir in order of their names. Symlinks are reported with their own metadata and not
+    /// followed. The root itself is not visited.
+    pub fn walk<F>(&self, mut f: F) -> Result<(), EphemeralError>
+    where
+        F: FnMut(&Path, &fs::Metadata),
+    {
+        walk_dir(&self.path, &self.path, &mut f)
+    }
+
+    /// Compares the project on disk with the dir at `other` and returns every difference, sorted by
+    /// path. Paths are relative to the two roots, dirs are compared by presence and files by
+    /// contents. File modes, timestamps and other metadata are not considered.
+    pub fn diff<P: AsRef<Path>>(&self, other: P) -> Result<Vec<TreeDiff>, EphemeralError> {
+        let left = entries(&self.path)?;
+        let right = entries(other.as_ref())?;
+
+        let mut diffs = vec![];
+        for (path, &is_dir) in left.iter() {
+            match right.get(path) {
+                None => diffs.push(TreeDiff::OnlyInLeft(path.clone())),
+                Some(&other_is_dir) if other_is_dir != is_dir => {
+                    diffs.push(TreeDiff::ContentsDiffer(path.clone()))
+                }
+                Some(_) if !is_dir => {
+                    if fs::read(self.path.join(path))? != fs::read(other.as_ref().join(path))? {
+                        diffs.push(TreeDiff::ContentsDiffer(path.clone()));
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        for path in right.keys().filter(|path| !left.contains_key(*path)) {
+            diffs.push(TreeDiff::OnlyInRight(path.clone()));
+        }
+        diffs.sort_by(|a, b| a.path().cmp(b.path()));
+
+        Ok(diffs)
+    }
+
+    /// Panics unless the project on disk matches the checked-in dir `golden` exactly, listing
+    /// every difference and the first differing line of changed text files. With the
+    /// `UPDATE_GOLDEN` environment variable set to anything but `0`, `golden` is replaced by a copy
+    /// of the project instead, so goldens can be regenerated with `UPDATE_GOLDEN=1 cargo test`.
+    pub fn assert_matches_golden<P: AsRef<Path>>(&self, golden: P) {
+        let update = env::var_os(UPDATE_GOLDEN).is_some_and(|value| value != "0");
+        let golden = golden.as_ref();
+        let diffs = match self.check_golden(golden, update) {
+            Ok(diffs) => diffs,
+            Err(err) => panic!("cannot compare with golden {}: {}", golden.display(), err),
+        };
+        if diffs.is_empty() {
+            return;
+        }
+
+        let mut summary = String::new();
+        for diff in diffs.iter() {
+            let (label, rel) = match *diff {
+                TreeDiff::OnlyInLeft(ref rel) => ("only in project", rel),
+                TreeDiff::OnlyInRight(ref rel) => ("only in golden", rel),
+                TreeDiff::ContentsDiffer(ref rel) => ("contents differ", rel),
+            };
+            summary.push_str(&format!("  {}: {}\n", label, rel.display()));
+            if let TreeDiff::ContentsDiffer(ref rel) = *diff {
+                let read = |root: &Path| fs::read_to_string(root.join(rel)).ok();
+                if let (Some(expected), Some(actual)) = (read(golden), read(&self.path)) {
+                    summary.push_str(&first_difference(&expected, &actual));
+                }
+            }
+        }
+        panic!(
+            "{} does not match golden {}:\n{}set {}=1 to regenerate it",
+            self.path.display(),
+            golden.display(),
+            summary,
+            UPDATE_GOLDEN
+        );
+    }
+
+    /// Compares the project with `golden`, or first replaces `golden` with a copy of the project
+    /// when `update` is set.
+    fn check_golden(&self, golden: &Path, update: bool) -> Result<Vec<TreeDiff>, EphemeralError> {
+        if update {
+            if golden.exists() {
+                remove_dir_all(golden)?;
+            }
+            copy_tree(&self.path, golden)?;
+        }
+
+        self.diff(golden)
+    }
+
+    /// Hashes the paths and contents of every file under the root with SHA-256, returning the
+    /// digest as hex, e.g. to pin a whole project in a snapshot test. Paths are relative, use `/`
+    /// and are sorted first, so the result only changes when a file is added, removed, renamed or
+    /// edited. Symlinks are hashed by their target, and dirs only through the files in them.
+    #[cfg(feature = "hash")]
+    pub fn checksum_tree(&self) -> Result<String, EphemeralError> {
+        let mut paths = vec![];
+        self.walk(|rel, metadata| {
+            if !metadata.is_dir() {
+                paths.push((rel.to_path_buf(), metadata.file_type().is_symlink()));
+            }
+        })?;
+        paths.sort();
+
+        let mut hasher = hash::Sha256::new();
+        for (rel, is_symlink) in paths {
+            let name: Vec<_> = rel
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect();
+            let path = self.path.join(&rel);
+            let (kind, contents) = if is_symlink {
+                (
+                    b'l',
+                    fs::read_link(path)?
+                        .to_string_lossy()
+                        .into_owned()
+                        .into_bytes(),
+                )
+            } else {
+                (b'f', fs::read(path)?)
+            };
+            hasher.update(&[kind]);
+            hasher.update(name.join("/").as_bytes());
+            hasher.update(&[0]);
+            hasher.update(&(contents.len() as u64).to_le_bytes());
+            hasher.update(&contents);
+        }
+
+        Ok(hasher.finish())
+    }
+
+    /// Copies the project on disk to `new_root`, which must not exist yet, and returns a project
+    /// rooted there. Everything under the root is copied, including files created by tools like
+    /// cargo. The copy keeps the dirs, files and settings of this project, moved under
+    /// `new_root`, so either project can be modified or cleared without affecting the other.
+    pub fn duplicate<P: AsRef<Path>>(&self, new_root: P) -> Result<Project, EphemeralError> {
+        let new_root = new_root.as_ref();
+        if fs::symlink_metadata(new_root).is_ok() {
+            let message = format!("{} already exists", new_root.display());
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, message).into());
+        }
+
+        let mut copy = self.clone();
+        copy.move_root(new_root.to_path_buf());
+        copy.created_parents = missing_parents(new_root);
+        copy_tree(&self.path, new_root)?;
+
+        Ok(copy.guard_if_clearing())
+    }
+
+    /// Runs cargo with `args` in the project root and waits for it to finish. The cargo binary is
+    /// the one set with `set_cargo_bin()`, else the `CARGO` environment variable when set (as it is
+    /// under `cargo test`), else `cargo` from `PATH`.
+    pub fn cargo<I, S>(&self, args: I) -> Result<Output, EphemeralError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.cargo_command(args)
+            .output()
+            .map_err(|err| self.cargo_error(err))
+    }
+
+    /// Like `cargo()`, but passes `--offline` before `args`.
+    pub fn cargo_offline<I, S>(&self, args: I) -> Result<Output, EphemeralError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let args: Vec<OsString> = args.into_iter().map(|arg| arg.as_ref().into()).collect();
+        self.cargo(iter::once(OsString::from("--offline")).chain(args))
+    }
+
+    /// Sets flags like `--offline`, `--locked` or `--frozen` that the process helpers pass to
+    /// cargo before the arguments of every invocation.
+    pub fn set_cargo_flags(&mut self, flags: &[&str]) {
+        self.cargo_flags = flags.iter().map(|flag| flag.to_string()).collect();
+    }
+
+    /// Makes the process helpers run the cargo binary at `path`, e.g. a specific toolchain's
+    /// cargo. They return `EphemeralError::NotFound` if it doesn't exist. To select a toolchain
+    /// with `+<toolchain>`, point this at rustup's `cargo` proxy.
+    pub fn set_cargo_bin<P: Into<PathBuf>>(&mut self, path: P) {
+        self.cargo_bin = Some(path.into());
+    }
+
+    /// Runs `cargo check` in the project. If it fails, returns `EphemeralError::CheckFailed` with a
+    /// report of the `Cargo.toml` on disk, the project's `tree()` and cargo's stderr, so a failing
+    /// fixture can be diagnosed from the error alone.
+    pub fn try_cargo_check(&self) -> Result<(), EphemeralError> {
+        let output = self.cargo(["check"])?;
+        if output.status.success() {
+            return Ok(());
+        }
+
+        let manifest = fs::read_to_string(self.path.join("Cargo.toml"))
+            .unwrap_or_else(|err| format!("<cannot read Cargo.toml: {}>\n", err));
+        Err(EphemeralError::CheckFailed(format!(
+            "--- Cargo.toml ---\n{}--- tree ---\n{}--- stderr ({}) ---\n{}",
+            manifest,
+            self.tree(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        )))
+    }
+
+    /// Like `try_cargo_check()`, but panics with the report instead of returning it.
+    pub fn assert_cargo_check(&self) {
+        if let Err(err) = self.try_cargo_check() {
+            panic!("{}", err);
+        }
+    }
+
+    /// Like `cargo()`, but kills cargo and returns `EphemeralError::Timeout` if it is still
+    /// running after `timeout`. On Unix the processes cargo spawned, e.g. a running build script,
+    /// are killed too; elsewhere only cargo itself is.
+    pub fn cargo_with_timeout<I, S>(
+        &self,
+        args: I,
+        timeout: Duration,
+    ) -> Result<Output, EphemeralError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut command = self.cargo_command(args);
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| self.cargo_error(err))?;
+        let stdout = read_in_background(child.stdout.take());
+        let stderr = read_in_background(child.stderr.take());
+
+        let status = match wait_until(&mut child, Instant::now() + timeout)? {
+            Some(status) => status,
+            None => {
+                kill_tree(&mut child);
+                return Err(EphemeralError::Timeout(timeout));
+            }
+        };
+
+        Ok(Output {
+            status,
+            stdout: stdout.join().expect("stdout reader panicked")?,
+            stderr: stderr.join().expect("stderr reader panicked")?,
+        })
+    }
+
+    fn cargo_command<I, S>(&self, args: I) -> Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut command = Command::new(self.cargo_bin());
+        command
+            .args(&self.cargo_flags)
+            .args(args)
+            .current_dir(&self.path);
+
+        command
+    }
+
+    fn cargo_bin(&self) -> OsString {
+        self.cargo_bin
+            .as_ref()
+            .map(OsString::from)
+            .or_else(|| env::var_os("CARGO"))
+            .unwrap_or_else(|| OsString::from("cargo"))
+    }
+
+    /// Maps a failure to start cargo, reporting a missing binary as `NotFound`.
+    fn cargo_error(&self, err: io::Error) -> EphemeralError {
+        match err.kind() {
+            io::ErrorKind::NotFound => EphemeralError::NotFound(PathBuf::from(self.cargo_bin())),
+            _ => err.into(),
+        }
+    }
+
+    /// Finds the file at `path` among the files added to the project.
+    pub(crate) fn file_mut(&mut self, path: &Path) -> Option<&mut File> {
+        self.dirs.iter_mut().find_map(|dir| dir.file_mut(path))
+    }
+
+    /// Writes `contents` to the file at `rel` (relative to the project root) on the filesystem,
+    /// creating missing parent dirs and replacing an existing file. Unlike the builders, this acts
+    /// on the built project right away, so a test can script a sequence of edits, e.g. for a file
+    /// watcher to observe. The project's dirs and files are left as they were.
+    pub fn write_file<P: AsRef<Path>>(
+        &self,
+        rel: P,
+        contents: &[u8],
+    ) -> Result<(), EphemeralError> {
+        let path = self.path.join(rel);
+        if let Some(parent) = path.parent() {
+            parent.to_path_buf().mkdir_p()?;
+        }
+
+        Ok(fs::write(path, contents)?)
+    }
+
+    /// Returns whether the file at `rel` (relative to the project root) on disk contains `needle`.
+    /// Invalid UTF-8 in the file is replaced before searching.
+    pub fn file_contains<P: AsRef<Path>>(
+        &self,
+        rel: P,
+        needle: &str,
+    ) -> Result<bool, EphemeralError> {
+        let contents = fs::read(self.path.join(rel))?;
+
+        Ok(String::from_utf8_lossy(&contents).contains(needle))
+    }
+
+    /// Panics unless the file at `rel` contains `needle`. The message shows the lines around the
+    /// longest start of `needle` found in the file, or the start of the file if there is none.
+    pub fn assert_file_contains<P: AsRef<Path>>(&self, rel: P, needle: &str) {
+        let path = self.path.join(rel);
+        let contents = match fs::read(&path) {
+            Ok(contents) => String::from_utf8_lossy(&contents).into_owned(),
+            Err(err) => panic!("cannot read {}: {}", path.display(), err),
+        };
+        if !contents.contains(needle) {
+            panic!(
+                "{} does not contain {:?}\n{}",
+                path.display(),
+                needle,
+                context(&contents, needle)
+            );
+        }
+    }
+
+    /// Returns whether nothing exists at `rel` (relative to the project root) on disk. A symlink
+    /// counts as present even if its target doesn't exist.
+    pub fn is_absent<P: AsRef<Path>>(&self, rel: P) -> bool {
+        fs::symlink_metadata(self.path.join(rel)).is_err()
+    }
+
+    /// Panics if anything exists at `rel`, like `is_absent()`.
+    pub fn assert_absent<P: AsRef<Path>>(&self, rel: P) {
+        let path = self.path.join(rel);
+        if let Ok(metadata) = fs::symlink_metadata(&path) {
+            let kind = if metadata.is_dir() { "dir" } else { "file" };
+            panic!("expected {} to be absent, found a {}", path.display(), kind);
+        }
+    }
+
+    /// Sets the modification time of every file under the project root on disk to now, e.g. to
+    /// make sources newer than artifacts seeded in `target/`. Dirs and symlinks are left as they
+    /// are.
+    pub fn touch_all(&self) -> Result<(), EphemeralError> {
+        self.touch_all_at(SystemTime::now())
+    }
+
+    /// Like `touch_all()`, but sets the modification times to `time`. Files are only opened for
+    /// reading, since setting the times takes owning the file rather than write access, so
+    /// read-only files are touched too.
+    pub fn touch_all_at(&self, time: SystemTime) -> Result<(), EphemeralError> {
+        for rel in entries(&self.path)?.into_keys() {
+            let path = self.path.join(rel);
+            if fs::symlink_metadata(&path)?.is_file() {
+                FsFile::open(path)?.set_modified(time)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the file at `rel` (relative to the project root) from the filesystem. Returns
+    /// `EphemeralError::NotFound` if there is no file at that path.
+    pub fn remove_file<P: AsRef<Path>>(&self, rel: P) -> Result<(), EphemeralError> {
+        let path = self.path.join(rel);
+        if !path.is_file() {
+            return Err(EphemeralError::NotFound(path));
+        }
+
+        Ok(fs::remove_file(path)?)
+    }
+
+    /// Deletes the dir at `rel` (relative to the project root) and everything inside it from the
+    /// filesystem. Returns `EphemeralError::NotFound` if there is no dir at that path.
+    pub fn remove_dir<P: AsRef<Path>>(&self, rel: P) -> Result<(), EphemeralError> {
+        let path = self.path.join(rel);
+        if !path.is_dir() {
+            return Err(EphemeralError::NotFound(path));
+        }
+
+        Ok(remove_dir_all(path)?)
+    }
 }
 
-impl FilePath for PathBuf {
-    fn mkdir_p(&self) -> Result<(), Box<dyn Error>> {
-        create_dir_all(self).map_err(|err| err.into())
+/// Clones the dirs, files and settings of the project, but never its `ClearGuard`.
+impl Clone for Project {
+    fn clone(&self) -> Self {
+        Project {
+            path: self.path.clone(),
+            dirs: self.dirs.clone(),
+            map: self.map.clone(),
+            clear_on_drop: self.clear_on_drop,
+            cargo_bin: self.cargo_bin.clone(),
+            verify_writes: self.verify_writes,
+            cargo_flags: self.cargo_flags.clone(),
+            strict_paths: self.strict_paths,
+            write_buffer_size: self.write_buffer_size,
+            incremental: self.incremental,
+            duplicate_dirs: self.duplicate_dirs,
+            created_parents: self.created_parents.clone(),
+            create_order: self.create_order.clone(),
+            default_mode: self.default_mode,
+            clear_guard: None,
+        }
+    }
+}
+
+impl Drop for Project {
+    fn drop(&mut self) {
+        if self.clears_on_drop(thread::panicking()) {
+            let _ = self.remove();
+        }
+    }
+}
+
+/// Summarizes the project as e.g. `Project(tmp): 5 files in 3 dirs`. Use `tree()` for the full
+/// structure.
+impl fmt::Display for Project {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Project({}): {} files in {} dirs",
+            self.path.display(),
+            self.file_count(),
+            self.dir_count()
+        )
+    }
+}
+
+/// A difference between a project and another dir, as returned by `Project::diff`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TreeDiff {
+    OnlyInLeft(PathBuf),
+    OnlyInRight(PathBuf),
+    ContentsDiffer(PathBuf),
+}
+
+impl TreeDiff {
+    /// Returns the relative path the difference was found at.
+    pub fn path(&self) -> &Path {
+        match *self {
+            TreeDiff::OnlyInLeft(ref path)
+            | TreeDiff::OnlyInRight(ref path)
+            | TreeDiff::ContentsDiffer(ref path) => path,
+        }
+    }
+}
+
+/// Lists every entry under `root` recursively, relative to `root`, and whether it is a dir.
+/// Symlinks are listed as entries but not followed.
+fn entries(root: &Path) -> io::Result<BTreeMap<PathBuf, bool>> {
+    let mut entries = BTreeMap::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let is_dir = entry.file_type()?.is_dir();
+            if let Ok(rel) = path.strip_prefix(root) {
+                entries.insert(rel.to_path_buf(), is_dir);
+            }
+            if is_dir {
+                pending.push(path);
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// The `/` separated path of `path` relative to `root` for archive entries, empty for the root
+/// itself. Fails with `EphemeralError::OutsideRoot` for paths that aren't under `root`.
+#[cfg(any(feature = "tar", feature = "zip"))]
+pub(crate) fn archive_name(root: &Path, path: &Path) -> Result<String, EphemeralError> {
+    let rel = path
+        .strip_prefix(root)
+        .map_err(|_| EphemeralError::OutsideRoot(path.to_path_buf()))?;
+    let mut parts = vec![];
+    for component in rel.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy()),
+            Component::CurDir => {}
+            _ => return Err(EphemeralError::OutsideRoot(path.to_path_buf())),
+        }
+    }
+
+    Ok(parts.join("/"))
+}
+
+/// Ancestors of `path` that don't exist, innermost first.
+fn missing_parents(path: &Path) -> Vec<PathBuf> {
+    path.ancestors()
+        .skip(1)
+        .take_while(|parent| !parent.as_os_str().is_empty() && !parent.exists())
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+/// Copies everything under `from` to `to`, creating `to`. Dirs get the permissions of their
+/// originals once their contents are in place.
+fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    create_dir_all(to)?;
+    let entries = entries(from)?;
+    for (rel, &is_dir) in entries.iter() {
+        if is_dir {
+            create_dir_all(to.join(rel))?;
+        } else {
+            copy_file(&from.join(rel), &to.join(rel))?;
+        }
+    }
+    let dirs = entries.iter().filter(|&(_, &is_dir)| is_dir);
+    for rel in dirs
+        .map(|(rel, _)| rel.as_path())
+        .rev()
+        .chain(iter::once(Path::new("")))
+    {
+        fs::set_permissions(to.join(rel), fs::metadata(from.join(rel))?.permissions())?;
+    }
+
+    Ok(())
+}
+
+/// Copies a file, or on Unix recreates a symlink pointing at the same target.
+#[cfg(unix)]
+fn copy_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(from)?.file_type().is_symlink() {
+        return std::os::unix::fs::symlink(fs::read_link(from)?, to);
+    }
+    fs::copy(from, to).map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn copy_file(from: &Path, to: &Path) -> io::Result<()> {
+    fs::copy(from, to).map(|_| ())
+}
+
+fn walk_dir(
+    root: &Path,
+    dir: &Path,
+    f: &mut dyn FnMut(&Path, &fs::Metadata),
+) -> Result<(), EphemeralError> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.sort();
+    for path in paths {
+        let metadata = fs::symlink_metadata(&path)?;
+        f(path.strip_prefix(root).unwrap_or(&path), &metadata);
+        if metadata.is_dir() {
+            walk_dir(root, &path, f)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Kills `child` and waits for it. On Unix this also kills the processes in its process group,
+/// which `cargo_with_timeout()` gives cargo, through `kill` since std can't signal a group. Every
+/// step is best effort, e.g. there may be no `kill` in `PATH`, so `child` is always killed and
+/// reaped and the caller can report the timeout whatever fails here.
+fn kill_tree(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        let group = format!("-{}", child.id());
+        let _ = Command::new("kill")
+            .args(["-KILL", "--", &group])
+            .stderr(Stdio::null())
+            .status();
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn read_in_background<R>(reader: Option<R>) -> thread::JoinHandle<io::Result<Vec<u8>>>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut buf = vec![];
+        if let Some(mut reader) = reader {
+            reader.read_to_end(&mut buf)?;
+        }
+        Ok(buf)
+    })
+}
+
+fn wait_until(
+    child: &mut Child,
+    deadline: Instant,
+) -> io::Result<Option<std::process::ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Describes the first line where `actual` differs from `expected`, for golden mismatches.
+fn first_difference(expected: &str, actual: &str) -> String {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => line += 1,
+            (None, None) => return "    only line endings differ\n".to_owned(),
+            (e, a) => {
+                return format!(
+                    "    line {}: expected {:?}, found {:?}\n",
+                    line,
+                    e.unwrap_or("<end of file>"),
+                    a.unwrap_or("<end of file>")
+                )
+            }
+        }
+    }
+}
+
+/// Lines of `contents` around the longest prefix of `needle` it contains, numbered and with the
+/// matching line marked by `>`.
+fn context(contents: &str, needle: &str) -> String {
+    let found = needle
+        .char_indices()
+        .map(|(i, c)| &needle[..i + c.len_utf8()])
+        .rev()
+        .find_map(|prefix| contents.find(prefix));
+    let (line, marked) = match found {
+        Some(at) => (contents[..at].matches('\n').count(), true),
+        None => (0, false),
+    };
+
+    contents
+        .lines()
+        .enumerate()
+        .skip(line.saturating_sub(2))
+        .take(if marked { line.min(2) + 3 } else { 5 })
+        .map(|(i, text)| {
+            let marker = if marked && i == line { '>' } else { ' ' };
+            format!("{} {:>4} | {}\n", marker, i + 1, text)
+        })
+        .collect()
+}
+
+/// Represents a dir in the filesystem. Accepts a path and contains a vector of files added.
+///
+/// To a Dir, you can attach files and child dirs created with `dir()`. Subdirectories can also
+/// be added directly to Project by specifying the parent dir in the path.
+#[derive(Clone, Debug)]
+pub struct Dir {
+    pub path: PathBuf,
+    files: Vec<File>,
+    dirs: Vec<Dir>,
+    readonly: bool,
+}
+
+impl Dir {
+    pub fn new<T: Into<PathBuf>>(path: T) -> Dir {
+        Dir::with_capacity(path, 0)
+    }
+
+    /// Creates a Dir with room for `files` files, so adding many files doesn't reallocate.
+    pub fn with_capacity<T: Into<PathBuf>>(path: T, files: usize) -> Dir {
+        Dir {
+            path: path.into(),
+            files: Vec::with_capacity(files),
+            dirs: vec![],
+            readonly: false,
+        }
+    }
+
+    /// Creates a Dir at `dest` holding the files of `dir`, which was compiled into the binary
+    /// with `embed_dir!`, so tests don't need the fixture's source tree at runtime. Files in
+    /// subdirs of `dir` go into child dirs.
+    #[cfg(feature = "embed")]
+    pub fn from_embedded<T: Into<PathBuf>>(dir: &embed::EmbeddedDir, dest: T) -> Dir {
+        dir.files.iter().fold(Dir::new(dest), |dest, file| {
+            let path = std::path::Path::new(file.path);
+            match (path.parent(), path.file_name()) {
+                (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+                    dest.dir(parent, |child| child.add_file(name, file.contents))
+                }
+                _ => dest.add_file(path, file.contents),
+            }
+        })
+    }
+
+    /// Adds a child dir at `path` (relative to this dir) and passes it to `f` to be populated,
+    /// so nested trees can be built without naming intermediate dirs:
+    ///
+    /// ```rust
+    /// use ephemeral::Dir;
+    ///
+    /// let dir = Dir::new("tmp").dir("src", |src| src.add_file("main.rs", b"fn main() {}"));
+    /// ```
+    pub fn dir<T, F>(mut self, path: T, f: F) -> Self
+    where
+        T: Into<PathBuf>,
+        F: FnOnce(Dir) -> Dir,
+    {
+        let child = f(Dir::new(self.resolve(path)));
+        self.dirs.push(child);
+
+        self
+    }
+
+    /// Adds a file to the Dir. Accepts any type that can be converted to a PathBuf just like the
+    /// rest of the crate. Contents of the file should be specified as well (in bytes). The path
+    /// may go through subdirs, e.g. `.github/workflows/ci.yml`, which are created with the file.
+    ///
+    /// Names are used as given, so they may contain spaces, Unicode and shell metacharacters. On
+    /// Unix any bytes other than `/` and NUL work too, passed as an `OsString` built with
+    /// `std::os::unix::ffi::OsStringExt::from_vec`. Windows names must be valid Unicode, so
+    /// invalid UTF-8 names are Unix-only. `Project::tree()` shows such names lossily.
+    pub fn add_file<T: Into<PathBuf>>(mut self, path: T, contents: &[u8]) -> Self {
+        let full_path = self.resolve(path);
+        self.files.push(File::new(full_path, contents));
+
+        self
+    }
+
+    /// Like `add_file()`, but the file gets the Unix permission bits `mode`, e.g. `0o755`, once
+    /// written, whatever the umask and `Builder::default_mode()`. Ignored on other platforms.
+    pub fn add_file_with_mode<T: Into<PathBuf>>(
+        mut self,
+        path: T,
+        contents: &[u8],
+        mode: u32,
+    ) -> Self {
+        let mut file = File::new(self.resolve(path), contents);
+        file.mode = Some(mode);
+        self.files.push(file);
+
+        self
+    }
+
+    /// Drops the file at `path` (resolved like in `add_file()`) from the dir, e.g. to undo a file
+    /// added by a shared helper. Does nothing if there is no such file. Files in child dirs are
+    /// left alone.
+    pub fn remove_file<T: Into<PathBuf>>(mut self, path: T) -> Self {
+        let full_path = self.resolve(path);
+        self.files.retain(|file| file.path != full_path);
+
+        self
+    }
+
+    /// Like `add_file()`, but the file is made read-only after it is written. `clear()` makes it
+    /// writable again before deleting it. Building the project a second time fails, since the
+    /// file can't be overwritten.
+    pub fn add_readonly_file<T: Into<PathBuf>>(mut self, path: T, contents: &[u8]) -> Self {
+        let mut file = File::new(self.resolve(path), contents);
+        file.readonly = true;
+        self.files.push(file);
+
+        self
+    }
+
+    /// Combines `other`, usually another definition of the same dir, into this one. Files of
+    /// `other` replace files of this dir at the same path, and child dirs at the same path are
+    /// merged the same way. Any other files and child dirs are added, keeping their paths. The
+    /// merged dir is read-only if either dir was.
+    pub fn merge(mut self, other: Dir) -> Self {
+        // Looked up by path rather than searched, as archives merge every dir however many files
+        // it has.
+        let mut files = BTreeMap::new();
+        for (i, file) in self.files.iter().enumerate() {
+            files.entry(file.path.clone()).or_insert(i);
+        }
+        for file in other.files {
+            match files.get(&file.path) {
+                Some(&i) => self.files[i] = file,
+                None => {
+                    files.insert(file.path.clone(), self.files.len());
+                    self.files.push(file);
+                }
+            }
+        }
+        for dir in other.dirs {
+            match self
+                .dirs
+                .iter()
+                .position(|existing| existing.path == dir.path)
+            {
+                Some(i) => {
+                    let existing = self.dirs.remove(i);
+                    self.dirs.insert(i, existing.merge(dir));
+                }
+                None => self.dirs.push(dir),
+            }
+        }
+        self.readonly |= other.readonly;
+
+        self
+    }
+
+    /// Makes the dir read-only once its files and child dirs are created, so nothing can be
+    /// added to or removed from it. `clear()` makes it writable again before deleting it.
+    pub fn readonly(mut self) -> Self {
+        self.readonly = true;
+
+        self
+    }
+
+    /// Adds a text file with every line ending in `contents` converted to `eol`, regardless of the
+    /// line endings used in `contents` or by the host OS.
+    pub fn add_file_with_eol<T: Into<PathBuf>>(
+        self,
+        path: T,
+        contents: &str,
+        eol: LineEnding,
+    ) -> Self {
+        let contents = contents.replace("\r\n", "\n");
+        let contents = match eol {
+            LineEnding::Lf => contents,
+            LineEnding::CrLf => contents.replace('\n', "\r\n"),
+        };
+
+        self.add_file(path, contents.as_bytes())
+    }
+
+    /// Adds a file with `value` serialized as TOML. Fails if `value` can't be written as a TOML
+    /// document, e.g. because it isn't a struct or map.
+    pub fn add_toml_file<T, V>(self, path: T, value: &V) -> Result<Self, EphemeralError>
+    where
+        T: Into<PathBuf>,
+        V: serde::Serialize,
+    {
+        let contents = match toml::Value::try_from(value)? {
+            table @ toml::Value::Table(_) => toml::to_string(&table)?,
+            _ => return Err(toml::ser::Error::UnsupportedType.into()),
+        };
+
+        Ok(self.add_file(path, contents.as_bytes()))
+    }
+
+    /// Adds a file with `value` serialized as JSON, indented by two spaces. The value goes
+    /// through `toml::Value`, so object keys are sorted, `None` fields are left out and the
+    /// value must be representable in TOML, e.g. no tuples or `null`s.
+    #[cfg(feature = "json")]
+    pub fn add_json_file<T, V>(self, path: T, value: &V) -> Result<Self, EphemeralError>
+    where
+        T: Into<PathBuf>,
+        V: serde::Serialize,
+    {
+        let contents = json::to_json(value)?;
+
+        Ok(self.add_file(path, contents.as_bytes()))
+    }
+
+    /// Adds a file whose contents are streamed from `reader` when the project is built, instead
+    /// of being held in memory. Useful for large fixtures. The reader is consumed by the first
+    /// `build()`, so building a clone of the project a second time returns an error.
+    pub fn add_file_reader<T, R>(mut self, path: T, reader: R) -> Self
+    where
+        T: Into<PathBuf>,
+        R: Read + Send + 'static,
+    {
+        let full_path = self.resolve(path);
+        self.files.push(File::from_reader(full_path, reader));
+
+        self
+    }
+
+    fn sort_entries(&mut self) {
+        self.files.sort_by(|a, b| a.path.cmp(&b.path));
+        self.dirs.sort_by(|a, b| a.path.cmp(&b.path));
+        for dir in self.dirs.iter_mut() {
+            dir.sort_entries();
+        }
+    }
+
+    fn move_root(&mut self, from: &Path, to: &Path) {
+        let rebase = |path: &mut PathBuf| {
+            if let Ok(rel) = path.strip_prefix(from) {
+                // Joining an empty `rel` would add a trailing separator, turning a file into a dir.
+                *path = if rel.as_os_str().is_empty() {
+                    to.to_path_buf()
+                } else {
+                    to.join(rel)
+                };
+            }
+        };
+        rebase(&mut self.path);
+        for file in self.files.iter_mut() {
+            rebase(&mut file.path);
+        }
+        for dir in self.dirs.iter_mut() {
+            dir.move_root(from, to);
+        }
+    }
+
+    /// Creates the dir, its files and all of its child dirs on the filesystem.
+    /// With `options.verify`, reads each file back after writing it and checks that it holds
+    /// what was written.
+    pub(crate) fn create(&self, options: &WriteOptions) -> Result<(), EphemeralError> {
+        self.path.mkdir_p()?;
+        for file in self.files.iter() {
+            file.create(&self.path, options)?;
+        }
+        for dir in self.dirs.iter() {
+            dir.create(options)?;
+        }
+        if self.readonly {
+            set_readonly(&self.path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds a file of exactly `len` bytes where the byte at offset `i` is `f(i)`. The contents are
+    /// generated while the file is written, so large fixtures are never held in memory.
+    pub fn add_generated_file<T, F>(self, path: T, len: usize, f: F) -> Self
+    where
+        T: Into<PathBuf>,
+        F: Fn(usize) -> u8 + Send + 'static,
+    {
+        self.add_file_reader(path, Generated { f, pos: 0, len })
+    }
+
+    /// Adds a file of `len` bytes that are all `byte`.
+    pub fn add_filler_file<T: Into<PathBuf>>(self, path: T, len: usize, byte: u8) -> Self {
+        self.add_generated_file(path, len, move |_| byte)
+    }
+
+    fn file_mut(&mut self, path: &Path) -> Option<&mut File> {
+        if let Some(file) = self.files.iter_mut().find(|file| file.path == path) {
+            return Some(file);
+        }
+        self.dirs.iter_mut().find_map(|dir| dir.file_mut(path))
+    }
+
+    fn resolve<T: Into<PathBuf>>(&self, path: T) -> PathBuf {
+        let path = path.into();
+        if path.is_relative() {
+            self.path.join(path)
+        } else {
+            path
+        }
+    }
+}
+
+/// Reader producing the contents of a generated file.
+struct Generated<F> {
+    f: F,
+    pos: usize,
+    len: usize,
+}
+
+impl<F: Fn(usize) -> u8> Read for Generated<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.len - self.pos);
+        for (i, byte) in buf[..n].iter_mut().enumerate() {
+            *byte = (self.f)(self.pos + i);
+        }
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+impl AsMut<Dir> for Dir {
+    fn as_mut(&mut self) -> &mut Dir {
+        self
+    }
+}
+
+/// Line ending used by `Dir::add_file_with_eol`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+/// Represents a file stored in the filesystem. Contains the path and the contents, either in
+/// bytes or as a reader that is streamed to disk on build.
+#[derive(Clone, Debug)]
+pub struct File {
+    pub path: PathBuf,
+    contents: Contents,
+    readonly: bool,
+    /// Unix mode set after writing, overriding `Builder::default_mode()`.
+    mode: Option<u32>,
+}
+
+impl File {
+    pub fn new<T: Into<PathBuf>>(path: T, contents: &[u8]) -> File {
+        File {
+            path: path.into(),
+            contents: Contents::Bytes(contents.into()),
+            readonly: false,
+            mode: None,
+        }
+    }
+
+    /// Writes the file into the existing `dir` it was added to, checking and protecting it as
+    /// `options` and `readonly` say. Subdirs of `dir` in the file's path, as in `add_file("a/b")`,
+    /// are created first.
+    fn create(&self, dir: &Path, options: &WriteOptions) -> Result<(), EphemeralError> {
+        if let Some(parent) = self.path.parent().filter(|&parent| parent != dir) {
+            create_dir_all(parent)?;
+        }
+        let written = self.write(options)?;
+        if options.verify {
+            verify_written(&self.path, &written)?;
+        }
+        if let Some(mode) = self.mode.or(options.default_mode) {
+            set_mode(&self.path, mode)?;
+        }
+        if self.readonly {
+            set_readonly(&self.path)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn from_reader<T, R>(path: T, reader: R) -> File
+    where
+        T: Into<PathBuf>,
+        R: Read + Send + 'static,
+    {
+        File {
+            path: path.into(),
+            contents: Contents::Source(Arc::new(Mutex::new(Some(Box::new(reader))))),
+            readonly: false,
+            mode: None,
+        }
+    }
+
+    /// Guesses whether the file is binary: its first 8 KiB contain a NUL byte or aren't valid
+    /// UTF-8. Files added from a reader can't be inspected without consuming them, so they are
+    /// reported as text.
+    pub fn is_binary(&self) -> bool {
+        self.binary_len().is_some()
+    }
+
+    /// Returns the length of the contents if the file `is_binary()`.
+    fn binary_len(&self) -> Option<usize> {
+        let bytes = match self.contents {
+            Contents::Bytes(ref bytes) => bytes,
+            Contents::Source(_) => return None,
+        };
+        let prefix = &bytes[..bytes.len().min(8192)];
+        let invalid = match std::str::from_utf8(prefix) {
+            Ok(_) => false,
+            // A multi-byte char cut off by the prefix isn't a sign of binary data.
+            Err(err) => err.error_len().is_some() || prefix.len() == bytes.len(),
+        };
+
+        if invalid || prefix.contains(&0) {
+            Some(bytes.len())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the contents if they are held in memory, i.e. the file wasn't added from a reader.
+    pub(crate) fn bytes_mut(&mut self) -> Option<&mut Vec<u8>> {
+        match self.contents {
+            Contents::Bytes(ref mut bytes) => Some(bytes),
+            Contents::Source(_) => None,
+        }
+    }
+
+    /// Writes the file and returns the number of bytes written. With a `map`, the whole contents
+    /// are read into memory and transformed.
+    pub(crate) fn write(&self, options: &WriteOptions) -> Result<Written, EphemeralError> {
+        if options.incremental {
+            return self.write_changed(options);
+        }
+        let mut file = Checksummed::new(BufWriter::with_capacity(
+            options.buffer_size,
+            FsFile::create(&self.path)?,
+        ));
+        match (&self.contents, options.map) {
+            (Contents::Bytes(bytes), None) => file.write_all(bytes)?,
+            (Contents::Bytes(bytes), Some(ContentMap(f))) => {
+                file.write_all(&f(&self.path, bytes))?
+            }
+            (Contents::Source(source), map) => {
+                let mut reader = source
+                    .lock()
+                    .map_err(|_| io::Error::other("file source is poisoned"))?
+                    .take()
+                    .ok_or_else(|| io::Error::other("file source already consumed"))?;
+                match map {
+                    Some(ContentMap(f)) => {
+                        let mut bytes = vec![];
+                        reader.read_to_end(&mut bytes)?;
+                        file.write_all(&f(&self.path, &bytes))?
+                    }
+                    None => {
+                        io::copy(&mut reader, &mut file)?;
+                    }
+                }
+            }
+        }
+        file.flush()?;
+
+        Ok(file.written())
+    }
+
+    /// Returns the contents passed through `map`, reading contents from a reader into memory.
+    fn contents_with(&self, map: Option<&ContentMap>) -> Result<Cow<'_, [u8]>, EphemeralError> {
+        Ok(match (&self.contents, map) {
+            (Contents::Bytes(bytes), None) => Cow::Borrowed(&bytes[..]),
+            (Contents::Bytes(bytes), Some(ContentMap(f))) => Cow::Owned(f(&self.path, bytes)),
+            (Contents::Source(source), map) => {
+                let mut bytes = vec![];
+                source
+                    .lock()
+                    .map_err(|_| io::Error::other("file source is poisoned"))?
+                    .take()
+                    .ok_or_else(|| io::Error::other("file source already consumed"))?
+                    .read_to_end(&mut bytes)?;
+                match map {
+                    Some(ContentMap(f)) => Cow::Owned(f(&self.path, &bytes)),
+                    None => Cow::Owned(bytes),
+                }
+            }
+        })
+    }
+
+    /// Writes the file unless it already exists with the same contents, compared by length first
+    /// and then byte by byte. Contents from a reader are read into memory to compare them.
+    fn write_changed(&self, options: &WriteOptions) -> Result<Written, EphemeralError> {
+        let bytes = self.contents_with(options.map)?;
+        let unchanged = match fs::metadata(&self.path) {
+            Ok(metadata) if metadata.is_file() && metadata.len() == bytes.len() as u64 => {
+                fs::read(&self.path)? == *bytes
+            }
+            _ => false,
+        };
+        if !unchanged {
+            let mut file =
+                BufWriter::with_capacity(options.buffer_size, FsFile::create(&self.path)?);
+            file.write_all(&bytes)?;
+            file.flush()?;
+        }
+
+        let mut written = Checksummed::new(io::sink());
+        written.write_all(&bytes)?;
+        Ok(written.written())
+    }
+}
+
+fn set_readonly(path: &Path) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn make_writable(path: &Path, mut permissions: fs::Permissions) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    permissions.set_mode(permissions.mode() | 0o200);
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(not(unix))]
+fn make_writable(path: &Path, mut permissions: fs::Permissions) -> io::Result<()> {
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    fs::set_permissions(path, permissions)
+}
+
+/// How `Dir::create` writes files.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct WriteOptions<'a> {
+    map: Option<&'a ContentMap>,
+    /// Whether to read each file back and check that it holds what was written.
+    verify: bool,
+    buffer_size: usize,
+    /// Whether to leave files that already have the right contents on disk untouched.
+    incremental: bool,
+    /// Mode set on files without one of their own, on Unix.
+    default_mode: Option<u32>,
+}
+
+impl Default for WriteOptions<'_> {
+    fn default() -> Self {
+        WriteOptions {
+            map: None,
+            verify: false,
+            buffer_size: DEFAULT_WRITE_BUFFER_SIZE,
+            incremental: false,
+            default_mode: None,
+        }
+    }
+}
+
+/// What `build()` does with dirs that were added at the same path more than once, set with
+/// `Builder::duplicate_dirs`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateDirs {
+    /// Creates every copy in the order they were added, so of the files at the same path the
+    /// last one added wins.
+    #[default]
+    Append,
+    /// Combines the copies with `Dir::merge()` into the first one before creating them.
+    Merge,
+    /// Fails with `EphemeralError::DuplicateFile` if any two files have the same path, before
+    /// anything is written.
+    Error,
+}
+
+/// Returns `dirs` and every dir nested in them, parents first.
+pub(crate) fn flatten(dirs: &[Dir]) -> Vec<&Dir> {
+    let mut flat = vec![];
+    let mut pending: Vec<&Dir> = dirs.iter().rev().collect();
+    while let Some(dir) = pending.pop() {
+        flat.push(dir);
+        pending.extend(dir.dirs.iter().rev());
+    }
+
+    flat
+}
+
+/// Combines dirs at the same path into the first of them, keeping the order of first appearance.
+pub(crate) fn merge_dirs(dirs: &[Dir]) -> Vec<Dir> {
+    let mut merged: Vec<Dir> = vec![];
+    for dir in dirs.iter().cloned() {
+        match merged.iter().position(|existing| existing.path == dir.path) {
+            Some(i) => {
+                let existing = merged.remove(i);
+                merged.insert(i, existing.merge(dir));
+            }
+            None => merged.push(dir),
+        }
+    }
+
+    merged
+}
+
+/// One write of `build()`: creating a dir, or writing a file into its dir.
+#[derive(Clone, Copy, Debug)]
+enum Step<'a> {
+    Dir(&'a Dir),
+    File(&'a File, &'a Dir),
+}
+
+impl Step<'_> {
+    fn path(&self) -> &Path {
+        match *self {
+            Step::Dir(dir) => &dir.path,
+            Step::File(file, _) => &file.path,
+        }
+    }
+}
+
+/// Lists the steps creating `dirs` in the default order, each with the index of the step creating
+/// the dir it is in, if any.
+fn steps(dirs: &[Dir]) -> Vec<(Step<'_>, Option<usize>)> {
+    let mut steps = vec![];
+    let mut pending: Vec<(&Dir, Option<usize>)> =
+        dirs.iter().rev().map(|dir| (dir, None)).collect();
+    while let Some((dir, parent)) = pending.pop() {
+        let index = steps.len();
+        steps.push((Step::Dir(dir), parent));
+        steps.extend(
+            dir.files
+                .iter()
+                .map(|file| (Step::File(file, dir), Some(index))),
+        );
+        pending.extend(dir.dirs.iter().rev().map(|child| (child, Some(index))));
+    }
+
+    steps
+}
+
+/// Orders `steps` so that each step comes after its dir, and every step at an `after` path after
+/// every step at the matching `before` path. Among the steps that may go next, the earliest in the
+/// default order goes first, so unconstrained steps keep their relative order.
+///
+/// Returns `EphemeralError::NotFound` for a constraint on a path no step creates, and
+/// `EphemeralError::CreateOrderCycle` if the constraints contradict each other.
+fn order_steps<'a>(
+    steps: &[(Step<'a>, Option<usize>)],
+    constraints: &[(PathBuf, PathBuf)],
+) -> Result<Vec<Step<'a>>, EphemeralError> {
+    let mut successors = vec![vec![]; steps.len()];
+    let mut predecessors = vec![0; steps.len()];
+    for (index, &(_, parent)) in steps.iter().enumerate() {
+        if let Some(parent) = parent {
+            successors[parent].push(index);
+            predecessors[index] += 1;
+        }
+    }
+    let matching = |path: &Path| -> Result<Vec<usize>, EphemeralError> {
+        let indices: Vec<_> = (0..steps.len())
+            .filter(|&index| steps[index].0.path() == path)
+            .collect();
+        if indices.is_empty() {
+            return Err(EphemeralError::NotFound(path.to_path_buf()));
+        }
+        Ok(indices)
+    };
+    for (after, before) in constraints.iter() {
+        let afters = matching(after)?;
+        for before in matching(before)? {
+            for &after in afters.iter() {
+                successors[before].push(after);
+                predecessors[after] += 1;
+            }
+        }
+    }
+
+    let mut ready: BTreeSet<usize> = (0..steps.len())
+        .filter(|&index| predecessors[index] == 0)
+        .collect();
+    let mut ordered = Vec::with_capacity(steps.len());
+    while let Some(index) = ready.iter().next().cloned() {
+        ready.remove(&index);
+        ordered.push(steps[index].0);
+        for &next in successors[index].iter() {
+            predecessors[next] -= 1;
+            if predecessors[next] == 0 {
+                ready.insert(next);
+            }
+        }
+    }
+    if let Some(index) = (0..steps.len()).find(|&index| predecessors[index] > 0) {
+        return Err(EphemeralError::CreateOrderCycle(
+            steps[index].0.path().to_path_buf(),
+        ));
+    }
+
+    Ok(ordered)
+}
+
+/// Capacity of the buffer files are written through, unless set with
+/// `Builder::write_buffer_size`.
+pub const DEFAULT_WRITE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Environment variable making `Project::assert_matches_golden` regenerate the golden dir.
+const UPDATE_GOLDEN: &str = "UPDATE_GOLDEN";
+
+/// Length and checksum of the bytes written to a file.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Written {
+    len: u64,
+    checksum: u64,
+}
+
+/// Passes writes on to `inner`, keeping the length and checksum of what went through.
+struct Checksummed<W> {
+    inner: W,
+    len: u64,
+    hasher: DefaultHasher,
+}
+
+impl<W: Write> Checksummed<W> {
+    fn new(inner: W) -> Self {
+        Checksummed {
+            inner,
+            len: 0,
+            hasher: DefaultHasher::new(),
+        }
+    }
+
+    fn written(&self) -> Written {
+        Written {
+            len: self.len,
+            checksum: self.hasher.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.write(&buf[..n]);
+        self.len += n as u64;
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads the file at `path` back and checks that it holds what was `written`.
+fn verify_written(path: &Path, written: &Written) -> Result<(), EphemeralError> {
+    let mut on_disk = Checksummed::new(io::sink());
+    io::copy(&mut FsFile::open(path)?, &mut on_disk)?;
+    let on_disk = on_disk.written();
+    if on_disk.len != written.len {
+        return Err(EphemeralError::WriteVerificationFailed {
+            path: path.to_path_buf(),
+            expected: written.len,
+            actual: on_disk.len,
+        });
+    }
+    if on_disk.checksum != written.checksum {
+        return Err(EphemeralError::WriteCorrupted(path.to_path_buf()));
+    }
+
+    Ok(())
+}
+
+/// A transform applied to file contents right before they are written.
+#[derive(Clone)]
+pub(crate) struct ContentMap(Arc<MapFn>);
+
+type MapFn = dyn Fn(&Path, &[u8]) -> Vec<u8> + Send + Sync;
+
+impl fmt::Debug for ContentMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ContentMap(..)")
+    }
+}
+
+type Source = Arc<Mutex<Option<Box<dyn Read + Send>>>>;
+
+/// Contents of a `File`. A `Source` is shared between clones and consumed by the first write.
+#[derive(Clone)]
+enum Contents {
+    Bytes(Vec<u8>),
+    Source(Source),
+}
+
+impl fmt::Debug for Contents {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Contents::Bytes(ref bytes) => f.debug_tuple("Bytes").field(bytes).finish(),
+            Contents::Source(_) => f.write_str("Source(..)"),
+        }
+    }
+}
+
+/// Adds common path-based function. This allows a path-based type to create directories. mkdir_p
+/// will recursively create a directory and all of its parent components if they are missing while
+/// mkdir will create a single directory.
+pub(crate) trait FilePath {
+    fn mkdir_p(&self) -> Result<(), EphemeralError>;
+}
+
+impl FilePath for PathBuf {
+    fn mkdir_p(&self) -> Result<(), EphemeralError> {
+        create_dir_all(self).map_err(|err| err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::Builder;
+    use crate::rust_tools::Edition;
+    use std::fs::metadata;
+
+    #[cfg(feature = "embed")]
+    #[test]
+    fn embedded_dirs_are_built_without_their_source() {
+        static FIXTURE: embed::EmbeddedDir = embed_dir!(".", ["Cargo.toml", "src/embed.rs"]);
+        let project = GenericBuilder::new("tmp_embedded")
+            .add_dir(Dir::from_embedded(&FIXTURE, "tmp_embedded"))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read("tmp_embedded/Cargo.toml").unwrap(),
+            include_bytes!("../Cargo.toml").to_vec()
+        );
+        assert_eq!(
+            std::fs::read("tmp_embedded/src/embed.rs").unwrap(),
+            include_bytes!("embed.rs").to_vec()
+        );
+        project.clear();
+    }
+
+    #[test]
+    fn clear_on_drop_removes_project_when_enabled() {
+        {
+            let project = GenericBuilder::new("tmp_drop_on")
+                .add_dir(Dir::new("tmp_drop_on/src").add_file("lib.rs", b""))
+                .clear_on_drop(true)
+                .build()
+                .unwrap();
+            assert!(project.path.join("src/lib.rs").exists());
+        }
+        assert!(!Path::new("tmp_drop_on").exists());
+
+        {
+            GenericBuilder::new("tmp_drop_off")
+                .clear_on_drop(false)
+                .build()
+                .unwrap();
+        }
+        assert!(Path::new("tmp_drop_off").exists());
+        Project::new("tmp_drop_off").clear();
+    }
+
+    #[test]
+    fn clear_on_drop_only_clears_the_built_project() {
+        let builder = GenericBuilder::new("tmp_drop_built")
+            .add_dir(Dir::new("tmp_drop_built").add_file("a", b""))
+            .clear_on_drop(true);
+        let project = builder.clone().build().unwrap();
+        drop(builder);
+        drop(project.clone());
+        project.clone().into_builder();
+        assert!(project.path.join("a").exists());
+
+        let copy = project.duplicate("tmp_drop_built_copy").unwrap();
+        drop(project);
+        assert!(!Path::new("tmp_drop_built").exists());
+        drop(copy);
+        assert!(!Path::new("tmp_drop_built_copy").exists());
+    }
+
+    #[test]
+    fn cargo_check_report_includes_manifest_tree_and_stderr() {
+        let builder = |path: &str, lib: &[u8]| {
+            RustBuilder::new(path)
+                .add_cargo_toml(Manifest::try_from("reported", "0.1.0", &[], None, None).unwrap())
+                .unwrap()
+                .add_dir(Dir::new(path).dir("src", |src| src.add_file("lib.rs", lib)))
+                .build()
+                .unwrap()
+        };
+
+        let project = builder("tmp_assert_check_ok", b"pub fn ok() {}");
+        project.assert_cargo_check();
+        project.clear();
+
+        let project = builder("tmp_assert_check_err", b"pub fn broken() -> u8 {}");
+        let report = match project.try_cargo_check() {
+            Err(EphemeralError::CheckFailed(report)) => report,
+            other => panic!("expected CheckFailed, got {:?}", other),
+        };
+        assert!(report.contains("name = \"reported\""));
+        assert!(report.contains("  src/\n    lib.rs\n"));
+        assert!(report.contains("mismatched types"));
+        project.clear();
+    }
+
+    #[test]
+    fn special_file_names_are_created_and_cleared() {
+        let names = [
+            "with space.txt",
+            "ünïcödé.rs",
+            "日本語",
+            "emoji 🦀",
+            "$dollar & 'quote'",
+        ];
+        let project = GenericBuilder::new("tmp_special_names")
+            .add_dir(
+                names
+                    .iter()
+                    .fold(Dir::new("tmp_special_names/dir with space"), |dir, name| {
+                        dir.add_file(name, name.as_bytes())
+                    }),
+            )
+            .build()
+            .unwrap();
+
+        for name in names.iter() {
+            let path = project.path.join("dir with space").join(name);
+            assert_eq!(fs::read(path).unwrap(), name.as_bytes());
+        }
+        assert!(project.tree().contains("    ünïcödé.rs\n"));
+        project.clear();
+        assert!(!Path::new("tmp_special_names").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn default_and_per_file_modes_are_applied() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let project = GenericBuilder::new("tmp_modes")
+            .add_dir(
+                Dir::new("tmp_modes")
+                    .add_file("default", b"")
+                    .add_file_with_mode("script.sh", b"#!/bin/sh\n", 0o755)
+                    .add_readonly_file("frozen", b""),
+            )
+            .default_mode(0o640)
+            .build()
+            .unwrap();
+
+        let mode = |rel: &str| {
+            metadata(project.path.join(rel))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777
+        };
+        assert_eq!(mode("default"), 0o640);
+        assert_eq!(mode("script.sh"), 0o755);
+        assert_eq!(mode("frozen"), 0o440);
+        project.clear();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_file_names_are_created_and_cleared() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let name = OsString::from_vec(vec![b'f', 0xff, 0xfe, b'o']);
+        let project = GenericBuilder::new("tmp_non_utf8_names")
+            .add_dir(Dir::new("tmp_non_utf8_names").add_file(&name, b"bytes"))
+            .build()
+            .unwrap();
+
+        assert_eq!(fs::read(project.path.join(&name)).unwrap(), b"bytes");
+        assert!(project.tree().contains("f\u{fffd}\u{fffd}o\n"));
+        project.clear();
+        assert!(!Path::new("tmp_non_utf8_names").exists());
+    }
+
+    #[test]
+    fn file_contains_finds_needles_and_reports_context() {
+        let project = GenericBuilder::new("tmp_file_contains")
+            .add_dir(
+                Dir::new("tmp_file_contains").add_file("lib.rs", b"a\nb\nc\nfn main() {}\nd\ne\nf"),
+            )
+            .build()
+            .unwrap();
+
+        assert!(project.file_contains("lib.rs", "fn main()").unwrap());
+        assert!(!project.file_contains("lib.rs", "fn mian()").unwrap());
+        assert!(project.file_contains("missing.rs", "").is_err());
+        project.assert_file_contains("lib.rs", "main() {}\nd");
+
+        let message = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            project.assert_file_contains("lib.rs", "fn mian()")
+        }))
+        .unwrap_err()
+        .downcast::<String>()
+        .unwrap();
+        assert_eq!(
+            *message,
+            format!(
+                "{} does not contain \"fn mian()\"\n{}",
+                Path::new("tmp_file_contains/lib.rs").display(),
+                [
+                    "     2 | b",
+                    "     3 | c",
+                    ">    4 | fn main() {}",
+                    "     5 | d",
+                    "     6 | e\n",
+                ]
+                .join("\n")
+            )
+        );
+        assert!(context("x\ny", "z").starts_with("     1 | x\n"));
+        project.clear();
+    }
+
+    #[test]
+    fn built_binaries_run_from_path() {
+        let project = RustBuilder::new("tmp_bin_on_path")
+            .add_cargo_toml(Manifest::try_from("on_path", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .add_bin_src(
+                "ephemeral_path_fixture",
+                Some("fn main() { print!(\"hi\"); }"),
+            )
+            .build()
+            .unwrap();
+        assert!(project.cargo_offline(["build"]).unwrap().status.success());
+        assert!(project.bin_path().is_dir());
+
+        let mut cmd = Command::new("ephemeral_path_fixture");
+        cmd.env("PATH", "/nonexistent");
+        project.with_bin_on_path(&mut cmd).unwrap();
+        let output = cmd.output().unwrap();
+        assert_eq!(output.stdout, b"hi");
+        project.clear();
+    }
+
+    #[test]
+    fn merge_combines_dirs_and_lets_other_win() {
+        let a = Dir::new("tmp_merge")
+            .add_file("shared", b"a")
+            .add_file("only_a", b"a")
+            .dir("src", |src| {
+                src.add_file("lib.rs", b"a").add_file("a.rs", b"")
+            });
+        let b = Dir::new("tmp_merge")
+            .add_file("shared", b"b")
+            .add_file("only_b", b"b")
+            .dir("src", |src| src.add_file("lib.rs", b"b"))
+            .dir("tests", |tests| tests.add_file("it.rs", b""))
+            .readonly();
+
+        let merged = a.merge(b);
+        assert!(merged.readonly);
+        assert_eq!(merged.files.len(), 3);
+        assert_eq!(merged.dirs.len(), 2);
+        let project = GenericBuilder::new("tmp_merge")
+            .add_dir(merged)
+            .build()
+            .unwrap();
+        let read = |rel: &str| fs::read(project.path.join(rel)).unwrap();
+        assert_eq!(read("shared"), b"b");
+        assert_eq!(read("only_a"), b"a");
+        assert_eq!(read("only_b"), b"b");
+        assert_eq!(read("src/lib.rs"), b"b");
+        assert_eq!(read("src/a.rs"), b"");
+        assert_eq!(read("tests/it.rs"), b"");
+        project.clear();
+    }
+
+    #[test]
+    fn assert_absent_accepts_missing_paths_only() {
+        let project = GenericBuilder::new("tmp_absent")
+            .add_dir(Dir::new("tmp_absent/src").add_file("lib.rs", b""))
+            .build()
+            .unwrap();
+
+        assert!(project.is_absent("src/main.rs"));
+        assert!(!project.is_absent("src/lib.rs"));
+        project.assert_absent("src/main.rs");
+        let message = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            project.assert_absent("src/lib.rs")
+        }))
+        .unwrap_err()
+        .downcast::<String>()
+        .unwrap();
+        assert_eq!(
+            *message,
+            format!(
+                "expected {} to be absent, found a file",
+                Path::new("tmp_absent/src/lib.rs").display()
+            )
+        );
+        project.clear();
+    }
+
+    #[test]
+    fn duplicate_dirs_follow_the_policy() {
+        let builder = |path: &str| {
+            GenericBuilder::new(path)
+                .add_dir(
+                    Dir::new(path)
+                        .add_file("shared", b"first")
+                        .add_file("a", b""),
+                )
+                .add_dir(
+                    Dir::new(path)
+                        .add_file("shared", b"second")
+                        .add_file("b", b""),
+                )
+        };
+
+        let project = builder("tmp_dup_append").build().unwrap();
+        assert_eq!(fs::read(project.path.join("shared")).unwrap(), b"second");
+        assert_eq!(project.dirs.len(), 3);
+        project.clear();
+
+        let merge = builder("tmp_dup_merge").duplicate_dirs(DuplicateDirs::Merge);
+        assert_eq!(
+            merge.project().plan(),
+            [
+                "tmp_dup_merge",
+                "tmp_dup_merge/shared",
+                "tmp_dup_merge/a",
+                "tmp_dup_merge/b"
+            ]
+            .map(PathBuf::from)
+        );
+        let project = merge.build().unwrap();
+        assert_eq!(fs::read(project.path.join("shared")).unwrap(), b"second");
+        assert!(project.path.join("a").exists() && project.path.join("b").exists());
+        assert_eq!(merge_dirs(&project.dirs).len(), 1);
+        project.clear();
+
+        match builder("tmp_dup_error")
+            .duplicate_dirs(DuplicateDirs::Error)
+            .build()
+        {
+            Err(EphemeralError::DuplicateFile(path)) => {
+                assert_eq!(path, Path::new("tmp_dup_error/shared"))
+            }
+            other => panic!("expected DuplicateFile, got {:?}", other),
+        }
+        assert!(!Path::new("tmp_dup_error").exists());
+        let project = GenericBuilder::new("tmp_dup_ok")
+            .add_dir(Dir::new("tmp_dup_ok").add_file("a", b""))
+            .add_dir(Dir::new("tmp_dup_ok").add_file("b", b""))
+            .duplicate_dirs(DuplicateDirs::Error)
+            .build()
+            .unwrap();
+        project.clear();
+    }
+
+    #[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, PartialEq)]
+    struct Settings {
+        name: String,
+        retries: u32,
+        tags: Vec<String>,
+        limits: BTreeMap<String, f64>,
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            name: "fixture \"one\"".to_owned(),
+            retries: 3,
+            tags: vec!["a".to_owned(), "b".to_owned()],
+            limits: iter::once(("cpu".to_owned(), 0.5)).collect(),
+        }
+    }
+
+    #[test]
+    fn toml_files_are_serialized_from_values() {
+        let project = GenericBuilder::new("tmp_toml_file")
+            .add_dir(
+                Dir::new("tmp_toml_file")
+                    .add_toml_file("data.toml", &settings())
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+
+        let contents = fs::read_to_string(project.path.join("data.toml")).unwrap();
+        assert_eq!(toml::from_str::<Settings>(&contents).unwrap(), settings());
+        assert!(Dir::new("tmp").add_toml_file("list.toml", &[1, 2]).is_err());
+        project.clear();
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_files_are_serialized_from_values() {
+        let dir = Dir::new("tmp")
+            .add_json_file("config.json", &settings())
+            .unwrap();
+        assert_eq!(
+            &*dir.files[0].contents_with(None).unwrap(),
+            "{\n  \"limits\": {\n    \"cpu\": 0.5\n  },\n  \"name\": \"fixture \\\"one\\\"\",\n  \
+             \"retries\": 3,\n  \"tags\": [\n    \"a\",\n    \"b\"\n  ]\n}\n"
+                .as_bytes()
+        );
+        let empty: Vec<u8> = vec![];
+        let dir = Dir::new("tmp").add_json_file("empty.json", &empty).unwrap();
+        assert_eq!(&*dir.files[0].contents_with(None).unwrap(), b"[]\n");
+        assert!(Dir::new("tmp")
+            .add_json_file("nan.json", &f64::NAN)
+            .is_err());
+    }
+
+    #[test]
+    fn walk_visits_every_entry_on_disk_in_order() {
+        let project = GenericBuilder::new("tmp_walk")
+            .add_dir(
+                Dir::new("tmp_walk")
+                    .add_file("b", b"12")
+                    .dir("a", |a| a.add_file("z", b"123").add_file("y", b"")),
+            )
+            .build()
+            .unwrap();
+        project.write_file("target/debug/out", b"1").unwrap();
+
+        let mut visited = vec![];
+        let (mut files, mut bytes) = (0, 0);
+        project
+            .walk(|path, metadata| {
+                visited.push(path.to_path_buf());
+                if metadata.is_file() {
+                    files += 1;
+                    bytes += metadata.len();
+                }
+            })
+            .unwrap();
+        assert_eq!((files, bytes), (4, 6));
+        assert_eq!(
+            visited,
+            [
+                "a",
+                "a/y",
+                "a/z",
+                "b",
+                "target",
+                "target/debug",
+                "target/debug/out"
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        );
+        project.clear();
+    }
+
+    #[test]
+    fn file_from_reader_streams_contents() {
+        let path = PathBuf::from("tmp_reader");
+        let dir = Dir::new(&path).add_file_reader("big", io::repeat(7u8).take(1 << 20));
+        dir.path.mkdir_p().unwrap();
+        dir.files[0].write(&WriteOptions::default()).unwrap();
+
+        assert_eq!(metadata(path.join("big")).unwrap().len(), 1 << 20);
+        assert!(dir.files[0].write(&WriteOptions::default()).is_err());
+
+        remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn remove_file_and_dir_after_build() {
+        let project = GenericBuilder::new("tmp_remove")
+            .add_dir(Dir::new("tmp_remove").add_file("Cargo.lock", b""))
+            .add_dir(Dir::new("tmp_remove/src").add_file("lib.rs", b""))
+            .build()
+            .unwrap();
+
+        project.remove_file("Cargo.lock").unwrap();
+        project.remove_dir("src").unwrap();
+        assert!(!project.path.join("Cargo.lock").exists());
+        assert!(!project.path.join("src").exists());
+
+        match project.remove_file("Cargo.lock") {
+            Err(EphemeralError::NotFound(path)) => {
+                assert_eq!(path, project.path.join("Cargo.lock"))
+            }
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+        assert!(project.remove_dir("src").is_err());
+
+        project.clear();
+    }
+
+    #[test]
+    fn incremental_builds_skip_unchanged_files() {
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let builder = |b: &[u8]| {
+            GenericBuilder::new("tmp_incremental")
+                .add_dir(
+                    Dir::new("tmp_incremental")
+                        .add_file("same", b"same")
+                        .add_file("changed", b)
+                        .add_file_reader("streamed", &b"streamed"[..]),
+                )
+                .incremental(true)
+        };
+
+        let project = builder(b"old").build().unwrap();
+        project.touch_all_at(old).unwrap();
+        let project = builder(b"new").build().unwrap();
+
+        let mtime = |name| {
+            metadata(project.path.join(name))
+                .unwrap()
+                .modified()
+                .unwrap()
+        };
+        assert_eq!(mtime("same"), old);
+        assert_eq!(mtime("streamed"), old);
+        assert_ne!(mtime("changed"), old);
+        assert_eq!(fs::read(project.path.join("changed")).unwrap(), b"new");
+        project.clear();
+    }
+
+    /// Yields `contents`, then replaces the file at `path` with `on_disk` before reporting the
+    /// end, as a faulty disk or a concurrent writer might.
+    struct Tamper {
+        path: &'static str,
+        contents: Option<&'static [u8]>,
+        on_disk: &'static [u8],
+    }
+
+    impl Read for Tamper {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.contents.take() {
+                Some(contents) => {
+                    buf[..contents.len()].copy_from_slice(contents);
+                    Ok(contents.len())
+                }
+                None => fs::write(self.path, self.on_disk).map(|_| 0),
+            }
+        }
+    }
+
+    #[test]
+    fn verify_writes_reads_files_back() {
+        let build = |on_disk: &'static [u8]| {
+            let result = GenericBuilder::new("tmp_verify_writes")
+                .add_dir(
+                    Dir::new("tmp_verify_writes")
+                        .add_filler_file("big", 10_000, b'x')
+                        .add_file_reader(
+                            "a",
+                            Tamper {
+                                path: "tmp_verify_writes/a",
+                                contents: Some(b"abc"),
+                                on_disk,
+                            },
+                        ),
+                )
+                .write_buffer_size(0)
+                .verify_writes(true)
+                .build();
+            Project::new("tmp_verify_writes").clear();
+            result
+        };
+
+        assert!(build(b"abc").is_ok());
+        match build(b"abd") {
+            Err(EphemeralError::WriteCorrupted(path)) => {
+                assert_eq!(path, PathBuf::from("tmp_verify_writes/a"))
+            }
+            other => panic!("expected WriteCorrupted, got {:?}", other),
+        }
+        match build(b"ab") {
+            Err(EphemeralError::WriteVerificationFailed {
+                path,
+                expected,
+                actual,
+            }) => assert_eq!(
+                (path, expected, actual),
+                (PathBuf::from("tmp_verify_writes/a"), 3, 2)
+            ),
+            other => panic!("expected WriteVerificationFailed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn readonly_files_and_dirs_are_created_and_cleared() {
+        let project = GenericBuilder::new("tmp_readonly")
+            .add_dir(
+                Dir::new("tmp_readonly")
+                    .add_readonly_file("locked.txt", b"locked")
+                    .add_file("open.txt", b"open")
+                    .dir("sealed", |sealed| {
+                        sealed
+                            .add_readonly_file("inner.txt", b"inner")
+                            .dir("deeper", |deeper| deeper.add_file("f", b""))
+                            .readonly()
+                    }),
+            )
+            .build()
+            .unwrap();
+
+        let readonly = |rel: &str| {
+            metadata(project.path.join(rel))
+                .unwrap()
+                .permissions()
+                .readonly()
+        };
+        assert!(readonly("locked.txt"));
+        assert!(!readonly("open.txt"));
+        assert!(readonly("sealed"));
+        assert!(readonly("sealed/inner.txt"));
+        assert!(!readonly("sealed/deeper"));
+        assert_eq!(
+            fs::read(project.path.join("locked.txt")).unwrap(),
+            b"locked"
+        );
+
+        let path = project.path.clone();
+        project.clear();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn open_adopts_an_existing_dir() {
+        let path = PathBuf::from("tmp_open");
+        path.join("src").mkdir_p().unwrap();
+        fs::write(path.join("src/lib.rs"), b"").unwrap();
+
+        let project = Project::open(&path);
+        assert_eq!(project.plan(), vec![path.clone()]);
+        assert_eq!(
+            project.clear_dry_run(),
+            [path.clone(), path.join("src"), path.join("src/lib.rs")]
+        );
+        project.write_file("README", b"").unwrap();
+        project.clear();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn validate_rejects_paths_outside_the_root() {
+        let outside = env::temp_dir().join("tmp_ephemeral_outside.txt");
+        let builder = GenericBuilder::new("tmp_outside")
+            .add_dir(Dir::new("tmp_outside/src").add_file("lib.rs", b""))
+            .add_dir(Dir::new("tmp_outside").add_file(&outside, b""));
+        assert!(builder.project().plan().contains(&outside));
+
+        match builder.project().validate() {
+            Err(EphemeralError::OutsideRoot(path)) => assert_eq!(path, outside),
+            other => panic!("expected OutsideRoot, got {:?}", other),
+        }
+        assert!(matches!(
+            builder.clone().strict_paths(true).build(),
+            Err(EphemeralError::OutsideRoot(_))
+        ));
+        assert!(!Path::new("tmp_outside").exists());
+        assert!(!outside.exists());
+
+        let sneaky =
+            GenericBuilder::new("tmp_outside").add_dir(Dir::new("tmp_outside/../tmp_sibling"));
+        assert!(sneaky.project().validate().is_err());
+        assert!(GenericBuilder::new("tmp_outside")
+            .add_dir(Dir::new("tmp_outside/src").add_file("lib.rs", b""))
+            .project()
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn dir_remove_file_prunes_before_build() {
+        let dir = Dir::new("tmp_dir_remove_file")
+            .add_file("keep.rs", b"")
+            .add_file("drop.rs", b"")
+            .remove_file("drop.rs")
+            .remove_file("missing.rs");
+        let project = GenericBuilder::new("tmp_dir_remove_file")
+            .add_dir(dir)
+            .build()
+            .unwrap();
+
+        assert!(project.path.join("keep.rs").exists());
+        assert!(!project.path.join("drop.rs").exists());
+        project.clear();
+    }
+
+    #[test]
+    fn write_buffer_size_does_not_change_contents() {
+        for &size in [0, 1, 7, DEFAULT_WRITE_BUFFER_SIZE].iter() {
+            let project = GenericBuilder::new("tmp_write_buffer")
+                .add_dir(
+                    Dir::new("tmp_write_buffer")
+                        .add_generated_file("gen", 100_000, |i| (i % 251) as u8)
+                        .add_file("small", b"small"),
+                )
+                .write_buffer_size(size)
+                .verify_writes(true)
+                .build()
+                .unwrap();
+
+            let generated = fs::read(project.path.join("gen")).unwrap();
+            assert!(generated
+                .iter()
+                .enumerate()
+                .all(|(i, &b)| b == (i % 251) as u8));
+            assert_eq!(fs::read(project.path.join("small")).unwrap(), b"small");
+            project.clear();
+        }
+    }
+
+    #[test]
+    fn persist_on_failure_keeps_project_only_when_panicking() {
+        let project = GenericBuilder::new("tmp_persist").build().unwrap();
+        assert!(!project.clears_on_drop(false));
+
+        let project = project.persist_on_failure();
+        assert!(project.clears_on_drop(false));
+        assert!(!project.clears_on_drop(true));
+
+        let plain = Project::new("tmp_persist_plain");
+        let plain = GenericBuilder::from_project(plain).clear_on_drop(true);
+        assert!(!plain.project().clears_on_drop(false));
+        assert!(!project.clone().clears_on_drop(false));
+
+        drop(project);
+        assert!(!Path::new("tmp_persist").exists());
+    }
+
+    #[test]
+    fn write_file_after_build() {
+        let project = GenericBuilder::new("tmp_write_file").build().unwrap();
+
+        project.write_file("src/lib.rs", b"pub fn a() {}").unwrap();
+        project.write_file("src/nested/mod.rs", b"").unwrap();
+        project.write_file("src/lib.rs", b"pub fn b() {}").unwrap();
+        assert_eq!(
+            fs::read(project.path.join("src/lib.rs")).unwrap(),
+            b"pub fn b() {}"
+        );
+        assert!(project.path.join("src/nested/mod.rs").is_file());
+        assert!(project.write_file("src/lib.rs/oops", b"").is_err());
+
+        project.clear();
+    }
+
+    #[test]
+    fn touch_all_bumps_file_mtimes() {
+        let project = GenericBuilder::new("tmp_touch_all")
+            .add_dir(Dir::new("tmp_touch_all/src").add_file("lib.rs", b""))
+            .add_dir(Dir::new("tmp_touch_all/target/debug").add_file("lib", b""))
+            .build()
+            .unwrap();
+        let lib = project.path.join("src/lib.rs");
+        let mut permissions = metadata(&lib).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&lib, permissions).unwrap();
+        let mtime = |rel: &str| {
+            metadata(project.path.join(rel))
+                .unwrap()
+                .modified()
+                .unwrap()
+        };
+
+        let past = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        project.touch_all_at(past).unwrap();
+        assert_eq!(mtime("src/lib.rs"), past);
+        assert_eq!(mtime("target/debug/lib"), past);
+        assert_ne!(mtime("src"), past);
+
+        project.touch_all().unwrap();
+        assert!(mtime("src/lib.rs") > past);
+        assert!(mtime("target/debug/lib") > past);
+        project.clear();
+    }
+
+    #[test]
+    fn cargo_flags_are_passed_to_every_invocation() {
+        let mut project = RustBuilder::new("tmp_cargo_flags")
+            .add_cargo_toml(Manifest::try_from("flags", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .add_dir(Dir::new("tmp_cargo_flags/src").add_file("lib.rs", b""))
+            .cargo_flags(&["--locked"])
+            .build()
+            .unwrap();
+
+        let output = project.cargo_offline(["check"]).unwrap();
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("--locked"));
+
+        project.set_cargo_flags(&[]);
+        assert!(project.cargo_offline(["check"]).unwrap().status.success());
+        project.set_cargo_flags(&["--locked"]);
+        assert!(project.cargo(["check"]).unwrap().status.success());
+        project.clear();
+    }
+
+    #[test]
+    fn set_cargo_bin_overrides_the_cargo_binary() {
+        let mut project = Project::new("tmp_cargo_bin");
+        project.set_cargo_bin("tmp_cargo_bin_missing/cargo");
+        match project.cargo(["--version"]) {
+            Err(EphemeralError::NotFound(path)) => {
+                assert_eq!(path, Path::new("tmp_cargo_bin_missing/cargo"))
+            }
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+        assert!(matches!(
+            project.cargo_with_timeout(["--version"], Duration::from_secs(5)),
+            Err(EphemeralError::NotFound(_))
+        ));
+
+        let mut project = project.into_builder().build().unwrap();
+        project.set_cargo_bin(env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo")));
+        let output = project.cargo(["--version"]).unwrap();
+        assert!(String::from_utf8_lossy(&output.stdout).starts_with("cargo "));
+        project.clear();
+    }
+
+    #[test]
+    fn cargo_with_timeout_kills_slow_builds() {
+        let project = RustBuilder::new("tmp_timeout")
+            .add_cargo_toml(Manifest::try_from("slow", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .add_dir(
+                Dir::new("tmp_timeout")
+                    .add_file(
+                        "build.rs",
+                        b"fn main() {\n    std::thread::sleep(std::time::Duration::from_secs(3));\n    \
+                          std::fs::write(\"finished\", \"\").unwrap();\n}\n",
+                    )
+                    .dir("src", |src| src.add_file("lib.rs", b"")),
+            )
+            .build()
+            .unwrap();
+
+        match project.cargo_with_timeout(["build", "--offline"], Duration::from_secs(2)) {
+            Err(EphemeralError::Timeout(timeout)) => assert_eq!(timeout, Duration::from_secs(2)),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        // The build script would have finished by now if it outlived cargo.
+        #[cfg(unix)]
+        {
+            thread::sleep(Duration::from_secs(3));
+            assert!(!project.path.join("finished").exists());
+        }
+        let output = project
+            .cargo_with_timeout(
+                ["metadata", "--offline", "--no-deps"],
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        assert!(output.status.success());
+
+        project.clear();
+    }
+
+    #[test]
+    fn project_without_dirs_can_be_built_and_cleared() {
+        let mut builder = RustBuilder::new("tmp_no_dirs")
+            .add_cargo_toml(Manifest::try_from("foo", "0.1.0", &[], None, None).unwrap())
+            .unwrap();
+        builder.project_mut().dirs.clear();
+
+        let project = builder.build().unwrap();
+        assert!(project.path.join("Cargo.toml").exists());
+        project.clear();
+
+        let path = PathBuf::from("tmp_no_dirs2");
+        path.mkdir_p().unwrap();
+        Project {
+            path: path.clone(),
+            dirs: vec![],
+            map: None,
+            clear_on_drop: false,
+            cargo_bin: None,
+            verify_writes: false,
+            cargo_flags: vec![],
+            strict_paths: false,
+            write_buffer_size: DEFAULT_WRITE_BUFFER_SIZE,
+            incremental: false,
+            duplicate_dirs: DuplicateDirs::default(),
+            created_parents: vec![],
+            create_order: vec![],
+            default_mode: None,
+            clear_guard: None,
+        }
+        .clear();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn add_file_with_eol_normalizes_line_endings() {
+        let contents = "a\nb\r\nc\n";
+        let project = GenericBuilder::new("tmp_eol")
+            .add_dir(
+                Dir::new("tmp_eol")
+                    .add_file_with_eol("lf", contents, LineEnding::Lf)
+                    .add_file_with_eol("crlf", contents, LineEnding::CrLf),
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(fs::read(project.path.join("lf")).unwrap(), b"a\nb\nc\n");
+        assert_eq!(
+            fs::read(project.path.join("crlf")).unwrap(),
+            b"a\r\nb\r\nc\r\n"
+        );
+        project.clear();
+    }
+
+    #[test]
+    fn golden_dirs_are_compared_and_regenerated() {
+        let project = GenericBuilder::new("tmp_golden_project")
+            .add_dir(
+                Dir::new("tmp_golden_project")
+                    .add_file("same", b"same")
+                    .dir("src", |src| {
+                        src.add_file("lib.rs", b"fn a() {}\nfn b() {}\n")
+                    }),
+            )
+            .build()
+            .unwrap();
+        let golden = Path::new("tmp_golden_expected");
+        assert!(project.check_golden(golden, true).unwrap().is_empty());
+        project.assert_matches_golden(golden);
+
+        project
+            .write_file("src/lib.rs", b"fn a() {}\nfn c() {}\n")
+            .unwrap();
+        project.write_file("extra", b"").unwrap();
+        fs::remove_file(project.path.join("same")).unwrap();
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            project.assert_matches_golden(golden)
+        }))
+        .unwrap_err();
+        assert_eq!(
+            panic.downcast_ref::<String>().unwrap(),
+            &[
+                "tmp_golden_project does not match golden tmp_golden_expected:",
+                "  only in project: extra",
+                "  only in golden: same",
+                "  contents differ: src/lib.rs",
+                "    line 2: expected \"fn b() {}\", found \"fn c() {}\"",
+                "set UPDATE_GOLDEN=1 to regenerate it",
+            ]
+            .join("\n")
+        );
+
+        assert!(project.check_golden(golden, true).unwrap().is_empty());
+        assert_eq!(
+            fs::read(golden.join("src/lib.rs")).unwrap(),
+            b"fn a() {}\nfn c() {}\n"
+        );
+        assert!(!golden.join("same").exists());
+        project.clear();
+        fs::remove_dir_all(golden).unwrap();
+    }
+
+    #[test]
+    fn diff_reports_differences_against_another_dir() {
+        let project = GenericBuilder::new("tmp_diff_left")
+            .add_dir(
+                Dir::new("tmp_diff_left")
+                    .add_file("same", b"same")
+                    .add_file("changed", b"left")
+                    .add_file("left", b""),
+            )
+            .build()
+            .unwrap();
+        let other = GenericBuilder::new("tmp_diff_right")
+            .add_dir(
+                Dir::new("tmp_diff_right")
+                    .add_file("same", b"same")
+                    .add_file("changed", b"right")
+                    .dir("right", |right| right.add_file("file", b"")),
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            project.diff(&other.path).unwrap(),
+            vec![
+                TreeDiff::ContentsDiffer(PathBuf::from("changed")),
+                TreeDiff::OnlyInLeft(PathBuf::from("left")),
+                TreeDiff::OnlyInRight(PathBuf::from("right")),
+                TreeDiff::OnlyInRight(PathBuf::from("right/file")),
+            ]
+        );
+        assert!(project.diff(&project.path).unwrap().is_empty());
+
+        project.clear();
+        other.clear();
+    }
+
+    #[test]
+    fn display_and_tree_summarize_the_project() {
+        let builder = GenericBuilder::new("tmp").add_dir(
+            Dir::new("tmp")
+                .add_file("Cargo.toml", b"")
+                .dir("src", |src| {
+                    src.add_file("lib.rs", b"")
+                        .dir("bin", |bin| bin.add_file("main.rs", b""))
+                }),
+        );
+        let project = builder.project();
+
+        assert_eq!(project.to_string(), "Project(tmp): 3 files in 3 dirs");
+        assert_eq!(
+            project.tree(),
+            "tmp/\n  Cargo.toml\n  src/\n    bin/\n      main.rs\n    lib.rs\n"
+        );
+    }
+
+    #[test]
+    fn is_binary_detects_nul_bytes_and_invalid_utf8() {
+        assert!(!File::new("a.rs", "fn main() { \"é\"; }".as_bytes()).is_binary());
+        assert!(!File::new("empty", b"").is_binary());
+        assert!(File::new("a.png", b"\x89PNG\r\n\x1a\n\0\0").is_binary());
+        assert!(File::new("latin1.txt", b"caf\xe9").is_binary());
+        assert!(!File::from_reader("reader", &b"\0"[..]).is_binary());
+
+        let cut = [vec![b'a'; 8191], "é".as_bytes().to_vec()].concat();
+        assert!(!File::new("cut.txt", &cut).is_binary());
+
+        let builder = GenericBuilder::new("tmp").add_dir(
+            Dir::new("tmp")
+                .add_file("blob", b"\0\x01\x02")
+                .add_file("txt", b"hi"),
+        );
+        assert_eq!(
+            builder.project().tree(),
+            "tmp/\n  blob (binary, 3 bytes)\n  txt\n"
+        );
+    }
+
+    #[test]
+    fn sort_entries_makes_plan_deterministic() {
+        let builder = GenericBuilder::new("tmp")
+            .add_dir(
+                Dir::new("tmp/src/bin")
+                    .add_file("b.rs", b"")
+                    .add_file("a.rs", b""),
+            )
+            .add_dir(
+                Dir::new("tmp/tests")
+                    .add_file("t.rs", b"")
+                    .dir("data", |data| data.add_file("in", b""))
+                    .dir("common", |common| common),
+            )
+            .add_dir(Dir::new("tmp/src").add_file("lib.rs", b""));
+        assert_eq!(builder.project().plan()[1], Path::new("tmp/src/bin"));
+
+        let plan = builder.sort_entries().project().plan();
+        let expected: Vec<PathBuf> = [
+            "tmp",
+            "tmp/src",
+            "tmp/src/lib.rs",
+            "tmp/src/bin",
+            "tmp/src/bin/a.rs",
+            "tmp/src/bin/b.rs",
+            "tmp/tests",
+            "tmp/tests/t.rs",
+            "tmp/tests/common",
+            "tmp/tests/data",
+            "tmp/tests/data/in",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(plan, expected);
+    }
+
+    #[test]
+    fn create_after_reorders_the_plan_and_build() {
+        let builder = GenericBuilder::new("tmp_create_after")
+            .add_dir(Dir::new("tmp_create_after/src").add_file("lib.rs", b""))
+            .add_dir(
+                Dir::new("tmp_create_after")
+                    .add_file("Cargo.toml", b"")
+                    .add_file("README.md", b""),
+            )
+            .add_dir(
+                Dir::new("tmp_create_after/ro")
+                    .add_file("a", b"")
+                    .readonly(),
+            )
+            .create_after("tmp_create_after/src", "tmp_create_after/Cargo.toml")
+            .create_after("tmp_create_after/ro/a", "tmp_create_after/src/lib.rs");
+
+        let root = PathBuf::from("tmp_create_after");
+        let expected = vec![
+            root.clone(),
+            root.clone(),
+            root.join("Cargo.toml"),
+            root.join("src"),
+            root.join("src/lib.rs"),
+            root.join("README.md"),
+            root.join("ro"),
+            root.join("ro/a"),
+        ];
+        assert_eq!(builder.project().plan(), expected);
+
+        let project = builder.build().unwrap();
+        assert!(root.join("src/lib.rs").is_file());
+        assert!(metadata(root.join("ro")).unwrap().permissions().readonly());
+        project.clear();
+    }
+
+    #[test]
+    fn create_after_rejects_cycles_and_unknown_paths() {
+        let builder = GenericBuilder::new("tmp_create_cycle").add_dir(
+            Dir::new("tmp_create_cycle")
+                .add_file("a", b"")
+                .add_file("b", b""),
+        );
+        let default_plan = builder.project().plan();
+
+        let cyclic = builder
+            .clone()
+            .create_after("tmp_create_cycle/a", "tmp_create_cycle/b")
+            .create_after("tmp_create_cycle/b", "tmp_create_cycle/a");
+        assert_eq!(cyclic.project().plan(), default_plan);
+        assert!(matches!(
+            cyclic.build(),
+            Err(EphemeralError::CreateOrderCycle(_))
+        ));
+        let result = builder
+            .clone()
+            .create_after("tmp_create_cycle", "tmp_create_cycle/a")
+            .build();
+        assert!(matches!(result, Err(EphemeralError::CreateOrderCycle(_))));
+        let result = builder
+            .create_after("tmp_create_cycle/a", "tmp_create_cycle/missing")
+            .build();
+        assert!(matches!(result, Err(EphemeralError::NotFound(_))));
+        assert!(!Path::new("tmp_create_cycle").exists());
+    }
+
+    #[test]
+    fn nested_dotfile_dirs_are_created() {
+        let project = GenericBuilder::new("tmp_dotfiles")
+            .add_dir(
+                Dir::new("tmp_dotfiles")
+                    .add_file(".github/workflows/ci.yml", b"on: push\n")
+                    .add_file(".config/tool/.hidden", b"x")
+                    .dir(".cargo", |cargo| cargo.add_file("config.toml", b"")),
+            )
+            .add_dir(Dir::new("tmp_dotfiles/.github/ISSUE_TEMPLATE").add_file("bug.md", b"bug"))
+            .build()
+            .unwrap();
+
+        let root = Path::new("tmp_dotfiles");
+        assert!(root.join(".github/workflows").is_dir());
+        assert_eq!(
+            fs::read(root.join(".github/workflows/ci.yml")).unwrap(),
+            b"on: push\n"
+        );
+        assert_eq!(fs::read(root.join(".config/tool/.hidden")).unwrap(), b"x");
+        assert!(root.join(".cargo/config.toml").is_file());
+        assert!(root.join(".github/ISSUE_TEMPLATE/bug.md").is_file());
+        let mut walked = vec![];
+        project
+            .walk(|rel, _| walked.push(rel.to_path_buf()))
+            .unwrap();
+        assert!(walked.contains(&PathBuf::from(".github/workflows/ci.yml")));
+        project.clear();
+        assert!(!root.exists());
+    }
+
+    #[cfg(feature = "hash")]
+    #[test]
+    fn checksum_tree_is_stable_and_tracks_changes() {
+        let build = |root: &str| {
+            GenericBuilder::new(root)
+                .add_dir(Dir::new(root).add_file("b", b"b").add_file("a", b"a"))
+                .add_dir(Dir::new(format!("{}/src", root)).add_file("lib.rs", b""))
+                .build()
+                .unwrap()
+        };
+        let project = build("tmp_checksum");
+        let checksum = project.checksum_tree().unwrap();
+        assert_eq!(checksum.len(), 64);
+        project.clear();
+
+        let project = build("tmp_checksum");
+        assert_eq!(project.checksum_tree().unwrap(), checksum);
+        let elsewhere = build("tmp_checksum_elsewhere");
+        assert_eq!(elsewhere.checksum_tree().unwrap(), checksum);
+        elsewhere.clear();
+
+        project.write_file("a", b"A").unwrap();
+        let edited = project.checksum_tree().unwrap();
+        assert_ne!(edited, checksum);
+        project.write_file("a", b"a").unwrap();
+        assert_eq!(project.checksum_tree().unwrap(), checksum);
+        fs::rename(project.path.join("b"), project.path.join("c")).unwrap();
+        assert_ne!(project.checksum_tree().unwrap(), checksum);
+        project.clear();
+    }
+
+    #[test]
+    fn duplicate_copies_the_tree_on_disk() {
+        let project = GenericBuilder::new("tmp_duplicate")
+            .add_dir(Dir::new("tmp_duplicate").add_file("lib.rs", b"pristine"))
+            .add_dir(Dir::new("tmp_duplicate/ro").add_file("a", b"a").readonly())
+            .build()
+            .unwrap();
+        project.write_file("target/generated", b"gen").unwrap();
+
+        let copy = project.duplicate("tmp_duplicate_parent/copy").unwrap();
+        assert_eq!(copy.path, Path::new("tmp_duplicate_parent/copy"));
+        assert!(project.diff(&copy.path).unwrap().is_empty());
+        assert!(copy.plan().contains(&copy.path.join("lib.rs")));
+        assert_eq!(
+            fs::read(copy.path.join("target/generated")).unwrap(),
+            b"gen"
+        );
+        assert!(metadata(copy.path.join("ro"))
+            .unwrap()
+            .permissions()
+            .readonly());
+        assert!(project.duplicate(&copy.path).is_err());
+
+        copy.write_file("lib.rs", b"mutated").unwrap();
+        assert_eq!(fs::read("tmp_duplicate/lib.rs").unwrap(), b"pristine");
+        assert_eq!(project.diff(&copy.path).unwrap().len(), 1);
+
+        copy.clear();
+        assert!(!Path::new("tmp_duplicate_parent").exists());
+        assert!(Path::new("tmp_duplicate/ro/a").is_file());
+        project.clear();
+    }
+
+    #[test]
+    fn clear_dry_run_lists_paths_without_deleting() {
+        let project = GenericBuilder::new("tmp_dry_run")
+            .add_dir(Dir::new("tmp_dry_run").dir("src", |src| src.add_file("lib.rs", b"")))
+            .build()
+            .unwrap();
+
+        let root = PathBuf::from("tmp_dry_run");
+        assert_eq!(
+            project.clear_dry_run(),
+            vec![root.clone(), root.join("src"), root.join("src/lib.rs")]
+        );
+        assert!(root.join("src/lib.rs").exists());
+
+        project.clear();
+        assert!(!root.exists());
+        assert!(Project::new("tmp_dry_run").clear_dry_run().is_empty());
+    }
+
+    #[test]
+    fn clear_removes_the_parents_build_created() {
+        let project = GenericBuilder::new("tmp_parents/a/b/project")
+            .add_dir(Dir::new("tmp_parents/a/b/project").add_file("file", b""))
+            .build()
+            .unwrap();
+        assert!(Path::new("tmp_parents/a/b/project/file").is_file());
+
+        project.clear();
+        assert!(!Path::new("tmp_parents").exists());
+    }
+
+    #[test]
+    fn clear_keeps_parents_that_existed_or_are_shared() {
+        fs::create_dir("tmp_parents_kept").unwrap();
+        let first = GenericBuilder::new("tmp_parents_kept/new/first")
+            .build()
+            .unwrap();
+        let second = GenericBuilder::new("tmp_parents_kept/new/second")
+            .build()
+            .unwrap();
+
+        first.clear();
+        assert!(Path::new("tmp_parents_kept/new/second").is_dir());
+        // `new` existed when `second` was built, so neither project removes it.
+        second.clear();
+        assert!(Path::new("tmp_parents_kept/new").is_dir());
+        fs::remove_dir_all("tmp_parents_kept").unwrap();
+    }
+
+    #[test]
+    fn generated_files_have_the_requested_length() {
+        let project = GenericBuilder::new("tmp_generated")
+            .add_dir(
+                Dir::new("tmp_generated")
+                    .add_generated_file("pattern", 10_000, |i| (i % 251) as u8)
+                    .add_filler_file("filler", 3 << 20, b'x')
+                    .add_filler_file("empty", 0, b'x'),
+            )
+            .build()
+            .unwrap();
+
+        let pattern = fs::read(project.path.join("pattern")).unwrap();
+        assert_eq!(pattern.len(), 10_000);
+        assert!(pattern
+            .iter()
+            .enumerate()
+            .all(|(i, &b)| b == (i % 251) as u8));
+        assert_eq!(
+            metadata(project.path.join("filler")).unwrap().len(),
+            3 << 20
+        );
+        assert_eq!(metadata(project.path.join("empty")).unwrap().len(), 0);
+        project.clear();
+    }
+
+    #[test]
+    fn canonical_root_resolves_the_built_root() {
+        let unbuilt = Project::new("tmp_canonical");
+        assert!(unbuilt.canonical_root().is_err());
+
+        let project = GenericBuilder::new("tmp_canonical").build().unwrap();
+        let root = project.canonical_root().unwrap();
+        assert!(root.is_absolute());
+        assert_eq!(
+            root,
+            env::current_dir()
+                .unwrap()
+                .canonicalize()
+                .unwrap()
+                .join("tmp_canonical")
+        );
+        project.clear();
+    }
+
+    #[test]
+    fn with_capacity_reserves_room() {
+        let dir = Dir::with_capacity("tmp_capacity", 100);
+        assert!(dir.files.capacity() >= 100);
+        assert!(dir.files.is_empty());
+
+        let builder = GenericBuilder::with_capacity("tmp_capacity", 50);
+        assert!(builder.project().dirs.capacity() >= 51);
+        assert_eq!(builder.project().dirs.len(), 1);
+    }
+
+    #[test]
+    fn modify_manifest_rewrites_cargo_toml() {
+        let project = RustBuilder::new("tmp_modify_manifest")
+            .add_cargo_toml(Manifest::try_from("foo", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        project
+            .modify_manifest(|manifest| {
+                manifest.package.version = semver::Version::new(0, 2, 0).into();
+                manifest.package.edition = Edition::Edition2015;
+            })
+            .unwrap();
+
+        let manifest = Manifest::from_path(project.path.join("Cargo.toml")).unwrap();
+        assert_eq!(manifest.package.name, "foo");
+        assert_eq!(
+            manifest.package.version.explicit(),
+            Some(&semver::Version::new(0, 2, 0))
+        );
+        assert_eq!(manifest.package.edition, Edition::Edition2015);
+        project.clear();
+
+        assert!(Project::new("tmp_no_manifest")
+            .modify_manifest(|_| {})
+            .is_err());
     }
 }
@@ -1,34 +1,81 @@
 use semver::Version;
-use serde::ser::Serializer;
-use serde_derive::Serialize;
-use std::collections::HashMap;
+use serde::de::{Deserialize, Deserializer, Error as DeError, MapAccess, Visitor};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::default::Default;
-use std::{error::Error, str::FromStr};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-#[cfg(feature = "nightly")]
-use std::convert::TryInto;
+use crate::error::EphemeralError;
+
+use std::convert::{TryFrom, TryInto};
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub name: String,
-    pub version: Version,
+    pub version: VersionField,
     pub authors: Vec<String>,
     pub edition: Edition,
+    pub description: Option<String>,
+    /// At most five crates.io search keywords, checked by `ConfigBuilder::build()`.
+    pub keywords: Option<Vec<String>>,
+    /// At most five crates.io category slugs, e.g. `command-line-utilities`.
+    pub categories: Option<Vec<String>>,
+    pub resolver: Option<Resolver>,
+    pub publish: Option<PublishSetting>,
+    pub autobins: Option<bool>,
+    pub autoexamples: Option<bool>,
+    pub autotests: Option<bool>,
+    pub autobenches: Option<bool>,
+    /// Name of the native library the package links to. Cargo requires a build script when this
+    /// is set.
+    pub links: Option<String>,
+    /// The `build` key: a build script other than `build.rs`, or `false` to turn off the
+    /// detection of `build.rs`.
+    pub build: Option<BuildScript>,
+    /// Path to the root of the workspace the package belongs to, for members that aren't inside
+    /// the workspace dir. Serialized as `workspace`.
+    #[serde(rename = "workspace")]
+    pub workspace_path: Option<PathBuf>,
 }
 
 impl Config {
+    /// Starts a `ConfigBuilder`, which is easier to read than `try_from()` and covers more fields.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Creates a config from positional arguments. Prefer `Config::builder()`, which names each
+    /// field at the call site.
     pub fn try_from(
         name: &str,
         version: &str,
         authors: &[&str],
         edition: Option<Edition>,
-    ) -> Result<Config, Box<Error>> {
+    ) -> Result<Config, EphemeralError> {
         let authors = authors.iter().map(|x| x.to_string()).collect();
         Ok(Config {
             name: name.to_owned(),
-            version: Version::from_str(version)?,
+            version: Version::from_str(version)?.into(),
             authors,
             edition: edition.into(),
+            description: None,
+            keywords: None,
+            categories: None,
+            resolver: None,
+            publish: None,
+            autobins: None,
+            autoexamples: None,
+            autotests: None,
+            autobenches: None,
+            links: None,
+            build: None,
+            workspace_path: None,
         })
     }
 }
@@ -37,24 +84,318 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             name: String::default(),
-            version: Version::from_str("0.0.0").unwrap(),
+            version: Version::new(0, 0, 0).into(),
             authors: vec![],
-            edition: Edition::Edition2018,
+            edition: Edition::default(),
+            description: None,
+            keywords: None,
+            categories: None,
+            resolver: None,
+            publish: None,
+            autobins: None,
+            autoexamples: None,
+            autotests: None,
+            autobenches: None,
+            links: None,
+            build: None,
+            workspace_path: None,
+        }
+    }
+}
+
+/// Written by hand so that an inherited version, which is a table, comes after the plain values
+/// of `[package]` as TOML requires. New fields need to be written here, `None`s skipped with
+/// `serialize_some()`.
+impl Serialize for Config {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut package = serializer.serialize_struct("Config", 16)?;
+        package.serialize_field("name", &self.name)?;
+        if let VersionField::Explicit(ref version) = self.version {
+            package.serialize_field("version", version)?;
+        }
+        package.serialize_field("authors", &self.authors)?;
+        package.serialize_field("edition", &self.edition)?;
+        serialize_some(&mut package, "description", &self.description)?;
+        serialize_some(&mut package, "keywords", &self.keywords)?;
+        serialize_some(&mut package, "categories", &self.categories)?;
+        serialize_some(&mut package, "resolver", &self.resolver)?;
+        serialize_some(&mut package, "publish", &self.publish)?;
+        serialize_some(&mut package, "autobins", &self.autobins)?;
+        serialize_some(&mut package, "autoexamples", &self.autoexamples)?;
+        serialize_some(&mut package, "autotests", &self.autotests)?;
+        serialize_some(&mut package, "autobenches", &self.autobenches)?;
+        serialize_some(&mut package, "links", &self.links)?;
+        serialize_some(&mut package, "build", &self.build)?;
+        serialize_some(&mut package, "workspace", &self.workspace_path)?;
+        if self.version == VersionField::Inherited {
+            package.serialize_field("version", &self.version)?;
+        }
+
+        package.end()
+    }
+}
+
+fn serialize_some<S, T>(state: &mut S, key: &'static str, value: &Option<T>) -> Result<(), S::Error>
+where
+    S: SerializeStruct,
+    T: Serialize,
+{
+    match *value {
+        Some(ref value) => state.serialize_field(key, value),
+        None => state.skip_field(key),
+    }
+}
+
+/// Value of the `version` key: a version, or `version.workspace = true` to inherit the one from
+/// `[workspace.package]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VersionField {
+    Explicit(Version),
+    Inherited,
+}
+
+impl VersionField {
+    /// Returns the version unless it is inherited.
+    pub fn explicit(&self) -> Option<&Version> {
+        match *self {
+            VersionField::Explicit(ref version) => Some(version),
+            VersionField::Inherited => None,
+        }
+    }
+}
+
+impl From<Version> for VersionField {
+    fn from(version: Version) -> Self {
+        VersionField::Explicit(version)
+    }
+}
+
+impl Serialize for VersionField {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *self {
+            VersionField::Explicit(ref version) => version.serialize(serializer),
+            VersionField::Inherited => {
+                let mut table = serializer.serialize_struct("VersionField", 1)?;
+                table.serialize_field("workspace", &true)?;
+                table.end()
+            }
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Default)]
+impl<'de> Deserialize<'de> for VersionField {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct VersionFieldVisitor;
+
+        impl<'de> Visitor<'de> for VersionFieldVisitor {
+            type Value = VersionField;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a version or { workspace = true }")
+            }
+
+            fn visit_str<E: DeError>(self, s: &str) -> Result<VersionField, E> {
+                Version::from_str(s)
+                    .map(VersionField::Explicit)
+                    .map_err(E::custom)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<VersionField, A::Error> {
+                let mut inherited = false;
+                while let Some(key) = map.next_key::<String>()? {
+                    if key != "workspace" {
+                        return Err(A::Error::unknown_field(&key, &["workspace"]));
+                    }
+                    inherited = map.next_value()?;
+                }
+                if inherited {
+                    Ok(VersionField::Inherited)
+                } else {
+                    Err(A::Error::custom("expected version.workspace = true"))
+                }
+            }
+        }
+
+        deserializer.deserialize_any(VersionFieldVisitor)
+    }
+}
+
+/// Builds a `Config` with chainable setters. Fields that aren't set keep the values of
+/// `Config::default()`.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+    version: Option<String>,
+}
+
+impl ConfigBuilder {
+    pub fn name(mut self, name: &str) -> Self {
+        self.config.name = name.to_owned();
+        self
+    }
+
+    /// Sets the version. It is parsed by `build()`.
+    pub fn version(mut self, version: &str) -> Self {
+        self.version = Some(version.to_owned());
+        self
+    }
+
+    /// Makes the package inherit its version from `[workspace.package]`, overriding `version()`.
+    pub fn version_from_workspace(mut self) -> Self {
+        self.config.version = VersionField::Inherited;
+        self.version = None;
+        self
+    }
+
+    pub fn author(mut self, author: &str) -> Self {
+        self.config.authors.push(author.to_owned());
+        self
+    }
+
+    pub fn edition(mut self, edition: Edition) -> Self {
+        self.config.edition = edition;
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.config.description = Some(description.to_owned());
+        self
+    }
+
+    /// Appends a keyword, checked by `build()`. Fields set directly on a `Config` aren't checked,
+    /// so invalid fixtures can still be written.
+    pub fn keyword(mut self, keyword: &str) -> Self {
+        self.config
+            .keywords
+            .get_or_insert_with(Vec::new)
+            .push(keyword.to_owned());
+        self
+    }
+
+    pub fn category(mut self, category: &str) -> Self {
+        self.config
+            .categories
+            .get_or_insert_with(Vec::new)
+            .push(category.to_owned());
+        self
+    }
+
+    pub fn resolver(mut self, resolver: Resolver) -> Self {
+        self.config.resolver = Some(resolver);
+        self
+    }
+
+    pub fn publish(mut self, publish: PublishSetting) -> Self {
+        self.config.publish = Some(publish);
+        self
+    }
+
+    pub fn links(mut self, links: &str) -> Self {
+        self.config.links = Some(links.to_owned());
+        self
+    }
+
+    pub fn build_script(mut self, build: BuildScript) -> Self {
+        self.config.build = Some(build);
+        self
+    }
+
+    /// Returns the config, or an error if the version isn't valid semver or the keywords and
+    /// categories break the crates.io limits: at most five of each, and keywords of up to 20
+    /// ASCII alphanumerics, `_`, `-` or `+`, starting with a letter.
+    pub fn build(self) -> Result<Config, EphemeralError> {
+        let mut config = self.config;
+        if let Some(version) = self.version {
+            config.version = Version::from_str(&version)?.into();
+        }
+        for (field, values) in [
+            ("keywords", &config.keywords),
+            ("categories", &config.categories),
+        ]
+        .iter()
+        {
+            let count = values.as_ref().map_or(0, Vec::len);
+            if count > 5 {
+                return Err(EphemeralError::InvalidMetadata(format!(
+                    "{} {}, at most 5 are allowed",
+                    count, field
+                )));
+            }
+        }
+        for keyword in config.keywords.iter().flatten() {
+            let valid = keyword.len() <= 20
+                && keyword.starts_with(|c: char| c.is_ascii_alphabetic())
+                && keyword
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_-+".contains(c));
+            if !valid {
+                return Err(EphemeralError::InvalidMetadata(format!(
+                    "invalid keyword \"{}\"",
+                    keyword
+                )));
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Manifest of a rust project, serialized as its `Cargo.toml`.
+///
+/// When parsing an existing manifest, tables that aren't modeled here (e.g. `[lints]`) are
+/// dropped.
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct Manifest {
     pub package: Config,
-    dependencies: Option<HashMap<String, Version>>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_sorted"
+    )]
+    dependencies: Option<HashMap<String, Dependency>>,
+    #[serde(
+        rename = "dev-dependencies",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_sorted"
+    )]
+    dev_dependencies: Option<HashMap<String, Dependency>>,
+    #[serde(
+        rename = "build-dependencies",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_sorted"
+    )]
+    build_dependencies: Option<HashMap<String, Dependency>>,
+    /// Explicit `[[bench]]` targets.
+    #[serde(rename = "bench", default, skip_serializing_if = "Vec::is_empty")]
+    pub benches: Vec<BenchTarget>,
+    /// Explicit `[[test]]` targets.
+    #[serde(rename = "test", default, skip_serializing_if = "Vec::is_empty")]
+    pub tests: Vec<TestTarget>,
 }
 
 impl Manifest {
     pub fn new(package: Config, dependencies: Option<HashMap<String, Version>>) -> Manifest {
+        let dependencies = dependencies.map(|dependencies| {
+            dependencies
+                .into_iter()
+                .map(|(name, version)| (name, version.into()))
+                .collect()
+        });
         Manifest {
             package,
             dependencies,
+            dev_dependencies: None,
+            build_dependencies: None,
+            benches: vec![],
+            tests: vec![],
         }
     }
 
@@ -64,10 +405,440 @@ impl Manifest {
         authors: &[&str],
         edition: Option<Edition>,
         dependencies: Option<HashMap<String, Version>>,
-    ) -> Result<Manifest, Box<Error>> {
+    ) -> Result<Manifest, EphemeralError> {
         let config = Config::try_from(name, version, authors, edition)?;
         Ok(Manifest::new(config, dependencies))
     }
+
+    /// Parses a manifest from the contents of a `Cargo.toml`.
+    pub fn from_toml_str(toml: &str) -> Result<Manifest, EphemeralError> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    /// Reads and parses the `Cargo.toml` at `path`, e.g. to use a real crate's manifest as the
+    /// starting point of a fixture.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Manifest, EphemeralError> {
+        Manifest::from_toml_str(&fs::read_to_string(path)?)
+    }
+
+    pub(crate) fn dependencies_mut(&mut self) -> &mut HashMap<String, Dependency> {
+        self.dependencies.get_or_insert_with(HashMap::new)
+    }
+
+    /// Serializes the manifest like the builders do, writing dependencies with more than a version
+    /// in the given `style`. With `DependencyStyle::Inline`, the dependency tables come after
+    /// the other tables modeled here.
+    pub fn to_toml_with(&self, style: DependencyStyle) -> Result<String, EphemeralError> {
+        if style == DependencyStyle::Table {
+            return to_toml(self);
+        }
+
+        let mut rest = self.clone();
+        let tables = [
+            ("dependencies", rest.dependencies.take()),
+            ("dev-dependencies", rest.dev_dependencies.take()),
+            ("build-dependencies", rest.build_dependencies.take()),
+        ];
+        let mut toml = to_toml(&rest)?;
+        for (header, table) in tables.iter() {
+            let table: BTreeMap<_, _> = match table {
+                Some(table) => table.iter().collect(),
+                None => continue,
+            };
+            toml.push_str(&format!("\n[{}]\n", header));
+            for (name, dependency) in table {
+                let value = match toml::Value::try_from(dependency)? {
+                    toml::Value::Table(fields) => {
+                        let fields: Vec<_> = fields
+                            .iter()
+                            .map(|(key, value)| format!("{} = {}", key, value))
+                            .collect();
+                        format!("{{ {} }}", fields.join(", "))
+                    }
+                    value => value.to_string(),
+                };
+                toml.push_str(&format!("{} = {}\n", name, value));
+            }
+        }
+
+        Ok(toml)
+    }
+
+    /// The dependencies of every table that come from crates.io: those with a version and no
+    /// `path`, `registry` or `git`.
+    pub fn crates_io_dependencies(&self) -> BTreeMap<&str, &Version> {
+        let tables = [
+            &self.dependencies,
+            &self.dev_dependencies,
+            &self.build_dependencies,
+        ];
+        tables
+            .iter()
+            .filter_map(|table| table.as_ref())
+            .flatten()
+            .filter(|(_, dependency)| {
+                dependency.path.is_none()
+                    && dependency.registry.is_none()
+                    && dependency.git.is_none()
+            })
+            .filter_map(|(name, dependency)| Some((name.as_str(), dependency.version.as_ref()?)))
+            .collect()
+    }
+
+    /// Renders a version 3 `Cargo.lock` for the package and its direct dependencies (regular, dev
+    /// and build), pinned to the versions in the manifest, without running cargo. Dependencies
+    /// without a version, from an alternative registry or from git are left out, and so are
+    /// transitive dependencies and checksums, which need the registry: cargo may still update the
+    /// lockfile of a package whose dependencies have dependencies of their own.
+    pub fn offline_lockfile(&self) -> Result<String, EphemeralError> {
+        let mut direct = BTreeMap::new();
+        let tables = [
+            &self.dependencies,
+            &self.dev_dependencies,
+            &self.build_dependencies,
+        ];
+        for (name, dependency) in tables.iter().filter_map(|table| table.as_ref()).flatten() {
+            if let (Some(ref version), None, None) =
+                (&dependency.version, &dependency.registry, &dependency.git)
+            {
+                direct.insert(name.clone(), (version.clone(), dependency.path.is_some()));
+            }
+        }
+
+        let mut packages: Vec<LockedPackage> = direct
+            .iter()
+            .map(|(name, &(ref version, is_path))| LockedPackage {
+                name: name.clone(),
+                version: version.to_string(),
+                source: if is_path {
+                    None
+                } else {
+                    Some(CRATES_IO_SOURCE.to_owned())
+                },
+                dependencies: vec![],
+            })
+            .collect();
+        packages.push(LockedPackage {
+            name: self.package.name.clone(),
+            version: self
+                .package
+                .version
+                .explicit()
+                .ok_or_else(|| {
+                    EphemeralError::InvalidMetadata(
+                        "the lockfile needs an explicit package version".to_owned(),
+                    )
+                })?
+                .to_string(),
+            source: None,
+            dependencies: direct.keys().cloned().collect(),
+        });
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let lockfile = to_toml(&Lockfile {
+            version: 3,
+            package: packages,
+        })?;
+        Ok(format!(
+            "# This file is automatically @generated by Cargo.\n\
+             # It is not intended for manual editing.\n{}",
+            lockfile
+        ))
+    }
+
+    /// Adds (or replaces) the dependency `name` in `[dependencies]`. `spec` can be a `Dependency`,
+    /// a `Version` or a version string like `"1.0.0"`, which returns an error if it isn't valid
+    /// semver.
+    pub fn add_dependency<D>(&mut self, name: &str, spec: D) -> Result<(), EphemeralError>
+    where
+        D: TryInto<Dependency>,
+        EphemeralError: From<D::Error>,
+    {
+        self.dependencies_mut()
+            .insert(name.to_owned(), spec.try_into()?);
+        Ok(())
+    }
+
+    /// Like `add_dependency()`, but for `[dev-dependencies]`.
+    pub fn add_dev_dependency<D>(&mut self, name: &str, spec: D) -> Result<(), EphemeralError>
+    where
+        D: TryInto<Dependency>,
+        EphemeralError: From<D::Error>,
+    {
+        self.dev_dependencies
+            .get_or_insert_with(HashMap::new)
+            .insert(name.to_owned(), spec.try_into()?);
+        Ok(())
+    }
+
+    /// Like `add_dependency()`, but for `[build-dependencies]`.
+    pub fn add_build_dependency<D>(&mut self, name: &str, spec: D) -> Result<(), EphemeralError>
+    where
+        D: TryInto<Dependency>,
+        EphemeralError: From<D::Error>,
+    {
+        self.build_dependencies
+            .get_or_insert_with(HashMap::new)
+            .insert(name.to_owned(), spec.try_into()?);
+        Ok(())
+    }
+}
+
+/// How `Manifest::to_toml_with()` writes dependencies that have more than a version.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DependencyStyle {
+    /// A `[dependencies.foo]` table per dependency.
+    #[default]
+    Table,
+    /// One line per dependency, e.g. `foo = { path = "../foo", version = "1.0.0" }`.
+    Inline,
+}
+
+const CRATES_IO_SOURCE: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+#[derive(Serialize)]
+struct Lockfile {
+    version: u32,
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Serialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    dependencies: Vec<String>,
+}
+
+/// A `[[bench]]` target. Unset fields are left to cargo's defaults.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BenchTarget {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub harness: Option<bool>,
+}
+
+/// A `[[test]]` target, e.g. an integration test with a custom harness. Unset fields are left to
+/// cargo's defaults.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestTarget {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub harness: Option<bool>,
+}
+
+/// A dependency of a package. A dependency with only a version is written as `foo = "1.0.0"`,
+/// any other as a `[dependencies.foo]` table.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(from = "DependencyRepr")]
+pub struct Dependency {
+    pub version: Option<Version>,
+    pub path: Option<String>,
+    /// Name of the alternative registry to take the crate from. The registry must be configured
+    /// for cargo, e.g. with `RustBuilder::registry()`.
+    pub registry: Option<String>,
+    /// URL of the git repository to take the crate from.
+    pub git: Option<String>,
+    pub features: Vec<String>,
+}
+
+impl Dependency {
+    /// Creates a dependency on the crate at `path`, relative to the dependent's manifest.
+    pub fn path<T: Into<String>>(path: T) -> Dependency {
+        Dependency {
+            path: Some(path.into()),
+            ..Dependency::default()
+        }
+    }
+}
+
+impl From<Version> for Dependency {
+    fn from(version: Version) -> Self {
+        Dependency {
+            version: Some(version),
+            ..Dependency::default()
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Dependency {
+    type Error = EphemeralError;
+
+    fn try_from(version: &'a str) -> Result<Self, Self::Error> {
+        Ok(Version::from_str(version)?.into())
+    }
+}
+
+impl Serialize for Dependency {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *self {
+            Dependency {
+                version: Some(ref version),
+                path: None,
+                registry: None,
+                git: None,
+                ref features,
+            } if features.is_empty() => version.serialize(serializer),
+            _ => {
+                let mut table = serializer.serialize_struct("Dependency", 5)?;
+                table.serialize_field("version", &self.version)?;
+                table.serialize_field("path", &self.path)?;
+                table.serialize_field("registry", &self.registry)?;
+                table.serialize_field("git", &self.git)?;
+                if !self.features.is_empty() {
+                    table.serialize_field("features", &self.features)?;
+                }
+                table.end()
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DependencyRepr {
+    Version(Version),
+    Detailed(DependencyTable),
+}
+
+/// The table form of a dependency. Keys that `Dependency` doesn't model, like `optional`, are
+/// ignored.
+#[derive(Deserialize)]
+struct DependencyTable {
+    #[serde(default)]
+    version: Option<Version>,
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    registry: Option<String>,
+    #[serde(default)]
+    git: Option<String>,
+    #[serde(default)]
+    features: Vec<String>,
+}
+
+impl From<DependencyRepr> for Dependency {
+    fn from(repr: DependencyRepr) -> Self {
+        match repr {
+            DependencyRepr::Version(version) => version.into(),
+            DependencyRepr::Detailed(table) => table.into(),
+        }
+    }
+}
+
+impl From<DependencyTable> for Dependency {
+    fn from(table: DependencyTable) -> Self {
+        Dependency {
+            version: table.version,
+            path: table.path,
+            registry: table.registry,
+            git: table.git,
+            features: table.features,
+        }
+    }
+}
+
+/// Parses a dependency spec as found in a manifest: either a version string like `"1.0.0"` or a
+/// table with `version`, `path`, `registry`, `git` and `features`. Versions must be exact, as
+/// elsewhere in this crate.
+impl TryFrom<toml::Value> for Dependency {
+    type Error = EphemeralError;
+
+    fn try_from(value: toml::Value) -> Result<Self, Self::Error> {
+        match value {
+            toml::Value::String(version) => Dependency::try_from(version.as_str()),
+            table @ toml::Value::Table(_) => Ok(table.try_into::<DependencyTable>()?.into()),
+            other => Err(EphemeralError::InvalidMetadata(format!(
+                "a dependency must be a version string or a table, found {}",
+                other.type_str()
+            ))),
+        }
+    }
+}
+
+/// Workspace section of a root manifest, serialized under `[workspace]`. Empty lists are omitted.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Workspace {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    #[serde(
+        rename = "default-members",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub default_members: Vec<String>,
+    /// The `[workspace.package]` table, which members can inherit keys from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package: Option<WorkspacePackage>,
+    /// Free-form `[workspace.metadata]` table, ignored by cargo but read by other tools.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<toml::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lints: Option<Lints>,
+}
+
+/// Keys of `[workspace.package]`, inherited by members with e.g. `version.workspace = true`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkspacePackage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<Version>,
+}
+
+/// Lint levels by tool (e.g. `rust` or `clippy`) and lint name, as in `[workspace.lints.rust]`.
+pub type Lints = BTreeMap<String, BTreeMap<String, LintLevel>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+    Forbid,
+}
+
+/// Manifest of a virtual workspace root, which only holds a `[workspace]` table.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WorkspaceManifest {
+    pub workspace: Workspace,
+}
+
+/// Serializes `value` to TOML ending with exactly one newline, so generated manifests are stable.
+pub(crate) fn to_toml<T: Serialize>(value: &T) -> Result<String, EphemeralError> {
+    let mut toml = toml::to_string(value)?;
+    let len = toml.trim_end_matches('\n').len();
+    toml.truncate(len);
+    toml.push('\n');
+
+    Ok(toml)
+}
+
+/// Serializes a map with its keys in sorted order so the generated manifest is stable. Entries
+/// that are tables are written after the plain values, as TOML requires.
+fn serialize_sorted<S, V>(
+    map: &Option<HashMap<String, V>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    V: Serialize,
+{
+    match *map {
+        Some(ref map) => {
+            toml::ser::tables_last(&map.iter().collect::<BTreeMap<_, _>>(), serializer)
+        }
+        None => serializer.serialize_none(),
+    }
 }
 
 #[cfg(feature = "nightly")]
@@ -79,26 +850,636 @@ impl TryInto<Vec<u8>> for Manifest {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Edition {
     Edition2015,
     Edition2018,
+    Edition2021,
+    Edition2024,
+}
+
+impl Edition {
+    /// Returns every edition, oldest first.
+    pub fn all() -> &'static [Edition] {
+        &[
+            Edition::Edition2015,
+            Edition::Edition2018,
+            Edition::Edition2021,
+            Edition::Edition2024,
+        ]
+    }
+
+    /// Returns the newest edition. It isn't the default one, see `Edition::default()`.
+    pub fn latest() -> Edition {
+        Edition::Edition2024
+    }
+}
+
+/// Edition 2021, used when no edition is set. Edition 2024 needs cargo 1.85 or later, so making
+/// it the default would break fixtures for anyone testing with an older toolchain.
+impl Default for Edition {
+    fn default() -> Self {
+        Edition::Edition2021
+    }
 }
 
 impl From<Option<Edition>> for Edition {
     fn from(ed: Option<Edition>) -> Self {
-        ed.unwrap_or(Edition::Edition2018)
+        ed.unwrap_or_default()
+    }
+}
+
+impl FromStr for Edition {
+    type Err = EphemeralError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "2015" => Ok(Edition::Edition2015),
+            "2018" => Ok(Edition::Edition2018),
+            "2021" => Ok(Edition::Edition2021),
+            "2024" => Ok(Edition::Edition2024),
+            _ => Err(EphemeralError::InvalidEdition(s.to_owned())),
+        }
     }
 }
 
-impl serde::ser::Serialize for Edition {
+impl Serialize for Edition {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// Formats the edition as cargo writes it, e.g. `2021`.
+impl fmt::Display for Edition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Edition::Edition2015 => "2015",
+            Edition::Edition2018 => "2018",
+            Edition::Edition2021 => "2021",
+            Edition::Edition2024 => "2024",
+        })
+    }
+}
+
+/// Accepts both `edition = "2021"` and `edition = 2021`, which some tools write.
+impl<'de> Deserialize<'de> for Edition {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct EditionVisitor;
+
+        impl<'de> Visitor<'de> for EditionVisitor {
+            type Value = Edition;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an edition like \"2021\" or 2021")
+            }
+
+            fn visit_str<E: DeError>(self, s: &str) -> Result<Edition, E> {
+                Edition::from_str(s).map_err(E::custom)
+            }
+
+            fn visit_i64<E: DeError>(self, n: i64) -> Result<Edition, E> {
+                self.visit_str(&n.to_string())
+            }
+
+            fn visit_u64<E: DeError>(self, n: u64) -> Result<Edition, E> {
+                self.visit_str(&n.to_string())
+            }
+        }
+
+        deserializer.deserialize_any(EditionVisitor)
+    }
+}
+
+/// Value of the `publish` key: either `publish = false` (or `true`) or the list of registries
+/// the package may be published to, e.g. `publish = ["my-registry"]`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PublishSetting {
+    Bool(bool),
+    Registries(Vec<String>),
+}
+
+/// Value of the `build` key: `build = "path/to/build.rs"` or `build = false`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BuildScript {
+    Path(String),
+    Disabled,
+}
+
+impl Serialize for BuildScript {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
         match *self {
-            Edition::Edition2015 => serializer.serialize_str("2015"),
-            Edition::Edition2018 => serializer.serialize_str("2018"),
+            BuildScript::Path(ref path) => serializer.serialize_str(path),
+            BuildScript::Disabled => serializer.serialize_bool(false),
         }
     }
 }
+
+impl<'de> Deserialize<'de> for BuildScript {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct BuildScriptVisitor;
+
+        impl<'de> Visitor<'de> for BuildScriptVisitor {
+            type Value = BuildScript;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a build script path or false")
+            }
+
+            fn visit_str<E: DeError>(self, s: &str) -> Result<BuildScript, E> {
+                Ok(BuildScript::Path(s.to_owned()))
+            }
+
+            fn visit_bool<E: DeError>(self, b: bool) -> Result<BuildScript, E> {
+                if b {
+                    Err(E::custom("build = true is not supported, use a path"))
+                } else {
+                    Ok(BuildScript::Disabled)
+                }
+            }
+        }
+
+        deserializer.deserialize_any(BuildScriptVisitor)
+    }
+}
+
+/// Version of cargo's feature resolver, serialized as `resolver = "2"`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Resolver {
+    V1,
+    V2,
+    V3,
+}
+
+impl FromStr for Resolver {
+    type Err = EphemeralError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1" => Ok(Resolver::V1),
+            "2" => Ok(Resolver::V2),
+            "3" => Ok(Resolver::V3),
+            _ => Err(EphemeralError::InvalidResolver(s.to_owned())),
+        }
+    }
+}
+
+impl Serialize for Resolver {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *self {
+            Resolver::V1 => serializer.serialize_str("1"),
+            Resolver::V2 => serializer.serialize_str("2"),
+            Resolver::V3 => serializer.serialize_str("3"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Resolver {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Resolver::from_str(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_builder_sets_fields() {
+        let config = Config::builder()
+            .name("built")
+            .version("1.2.3")
+            .author("a")
+            .author("b")
+            .edition(Edition::Edition2015)
+            .description("A fixture")
+            .resolver(Resolver::V2)
+            .build()
+            .unwrap();
+
+        let toml = to_toml(&Manifest::new(config, None)).unwrap();
+        assert_eq!(
+            toml,
+            "[package]\nname = \"built\"\nversion = \"1.2.3\"\nauthors = [\"a\", \"b\"]\n\
+             edition = \"2015\"\ndescription = \"A fixture\"\nresolver = \"2\"\n"
+        );
+        assert!(Config::builder().version("one").build().is_err());
+        assert_eq!(
+            Config::builder().build().unwrap().version,
+            VersionField::Explicit(Version::new(0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn config_builder_sets_and_checks_keywords_and_categories() {
+        let config = Config::builder()
+            .name("tagged")
+            .keyword("cli")
+            .keyword("test-fixtures")
+            .category("development-tools::testing")
+            .build()
+            .unwrap();
+
+        let toml = to_toml(&Manifest::new(config, None)).unwrap();
+        assert!(toml.contains(
+            "keywords = [\"cli\", \"test-fixtures\"]\n\
+             categories = [\"development-tools::testing\"]\n"
+        ));
+
+        let too_many = (0..6).fold(Config::builder(), |builder, i| {
+            builder.keyword(&format!("k{}", i))
+        });
+        assert!(matches!(
+            too_many.build(),
+            Err(EphemeralError::InvalidMetadata(ref message)) if message == "6 keywords, at most 5 are allowed"
+        ));
+        let too_many = (0..6).fold(Config::builder(), |builder, i| {
+            builder.category(&format!("c{}", i))
+        });
+        assert!(too_many.build().is_err());
+        for keyword in ["1st", "has space", "ünï", "a-very-long-keyword-indeed"].iter() {
+            assert!(Config::builder().keyword(keyword).build().is_err());
+        }
+        assert!(Config::builder().keyword("c++").build().is_ok());
+    }
+
+    #[test]
+    fn build_script_serializes_as_path_or_false() {
+        let manifest = |build| {
+            let config = Config::builder()
+                .name("built")
+                .build_script(build)
+                .build()
+                .unwrap();
+            to_toml(&Manifest::new(config, None)).unwrap()
+        };
+
+        let path = manifest(BuildScript::Path("scripts/build.rs".to_owned()));
+        assert!(path.contains("\nbuild = \"scripts/build.rs\"\n"));
+        let disabled = manifest(BuildScript::Disabled);
+        assert!(disabled.contains("\nbuild = false\n"));
+
+        for toml in [path, disabled].iter() {
+            let parsed = Manifest::from_toml_str(toml).unwrap();
+            assert_eq!(&to_toml(&parsed).unwrap(), toml);
+        }
+        assert!(Manifest::from_toml_str("[package]\nname = \"a\"\nbuild = true\n").is_err());
+    }
+
+    #[test]
+    fn inherited_version_is_written_as_workspace_table() {
+        let config = Config::builder()
+            .name("member")
+            .version("1.0.0")
+            .version_from_workspace()
+            .description("inherits")
+            .build()
+            .unwrap();
+        let toml = to_toml(&Manifest::new(config, None)).unwrap();
+        assert_eq!(
+            toml,
+            format!(
+                "[package]\nname = \"member\"\nauthors = []\nedition = \"{}\"\n\
+                 description = \"inherits\"\n\n[package.version]\nworkspace = true\n",
+                Edition::default()
+            )
+        );
+
+        let parsed = Manifest::from_toml_str(&toml).unwrap();
+        assert_eq!(parsed.package.version, VersionField::Inherited);
+        let dotted = Manifest::from_toml_str("[package]\nname = \"a\"\nversion.workspace = true\n");
+        assert_eq!(dotted.unwrap().package.version, VersionField::Inherited);
+        assert!(Manifest::from_toml_str("[package]\nversion.workspace = false\n").is_err());
+        let extra = "[package]\nversion = { workspace = true, path = \"..\" }\n";
+        let err = Manifest::from_toml_str(extra).unwrap_err().to_string();
+        assert!(err.contains("unknown field `path`"), "{}", err);
+        assert!(parsed.offline_lockfile().is_err());
+    }
+
+    #[test]
+    fn editions_can_be_compared() {
+        assert_eq!(Edition::Edition2018, Edition::Edition2018);
+        assert_ne!(Edition::Edition2015, Edition::Edition2018);
+    }
+
+    #[test]
+    fn all_editions_are_listed_in_order() {
+        let index = |edition: &Edition| match *edition {
+            Edition::Edition2015 => 0,
+            Edition::Edition2018 => 1,
+            Edition::Edition2021 => 2,
+            Edition::Edition2024 => 3,
+        };
+
+        let indices: Vec<_> = Edition::all().iter().map(index).collect();
+        assert_eq!(indices, (0..indices.len()).collect::<Vec<_>>());
+        assert!(Edition::all().contains(&Edition::latest()));
+        assert_eq!(Edition::all().last(), Some(&Edition::latest()));
+        assert_eq!(Manifest::default().package.edition, Edition::Edition2021);
+        assert_eq!(Edition::from(None), Edition::Edition2021);
+    }
+
+    #[test]
+    fn offline_lockfile_pins_direct_dependencies() {
+        let mut manifest = Manifest::try_from("root", "0.2.0", &[], None, None).unwrap();
+        manifest.add_dependency("serde", "1.0.100").unwrap();
+        manifest
+            .add_dev_dependency(
+                "local",
+                Dependency {
+                    version: Some(Version::new(0, 1, 0)),
+                    path: Some("../local".to_owned()),
+                    ..Dependency::default()
+                },
+            )
+            .unwrap();
+        manifest
+            .add_build_dependency("unpinned", Dependency::path("../unpinned"))
+            .unwrap();
+
+        let lockfile = manifest.offline_lockfile().unwrap();
+        assert!(lockfile.starts_with("# This file is automatically @generated by Cargo.\n"));
+        let lockfile: toml::Value = lockfile.parse().unwrap();
+        assert_eq!(lockfile["version"].as_integer(), Some(3));
+
+        let packages = lockfile["package"].as_array().unwrap();
+        let names: Vec<_> = packages
+            .iter()
+            .map(|package| package["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["local", "root", "serde"]);
+        let root = &packages[1];
+        assert_eq!(root["version"].as_str(), Some("0.2.0"));
+        assert!(root.get("source").is_none());
+        assert_eq!(
+            root["dependencies"].clone(),
+            toml::Value::from(vec!["local", "serde"])
+        );
+        assert!(packages[0].get("source").is_none());
+        assert_eq!(packages[2]["source"].as_str(), Some(CRATES_IO_SOURCE));
+    }
+
+    #[test]
+    fn dependency_styles_write_tables_or_inline_entries() {
+        let mut manifest = Manifest::try_from("styled", "0.1.0", &[], None, None).unwrap();
+        manifest.add_dependency("a", "1.0.0").unwrap();
+        manifest
+            .add_dependency(
+                "b",
+                Dependency {
+                    version: Some(Version::new(0, 2, 0)),
+                    path: Some("../b".to_owned()),
+                    ..Dependency::default()
+                },
+            )
+            .unwrap();
+        manifest
+            .add_dev_dependency("c", Dependency::path("../c"))
+            .unwrap();
+
+        let package = to_toml(&Manifest::new(manifest.package.clone(), None)).unwrap();
+        let table = manifest.to_toml_with(DependencyStyle::Table).unwrap();
+        assert_eq!(table, to_toml(&manifest).unwrap());
+        assert_eq!(
+            &table[package.len()..],
+            "\n[dependencies]\na = \"1.0.0\"\n\n[dependencies.b]\nversion = \"0.2.0\"\n\
+             path = \"../b\"\n[dev-dependencies.c]\npath = \"../c\"\n"
+        );
+
+        let inline = manifest.to_toml_with(DependencyStyle::Inline).unwrap();
+        assert_eq!(
+            &inline[package.len()..],
+            "\n[dependencies]\na = \"1.0.0\"\nb = { path = \"../b\", version = \"0.2.0\" }\n\
+             \n[dev-dependencies]\nc = { path = \"../c\" }\n"
+        );
+        let parsed = Manifest::from_toml_str(&inline).unwrap();
+        assert_eq!(to_toml(&parsed).unwrap(), table);
+    }
+
+    #[test]
+    fn add_dependency_mutators_fill_each_table() {
+        let mut manifest = Manifest::try_from("deps", "0.1.0", &[], None, None).unwrap();
+        manifest.add_dependency("a", "1.0.0").unwrap();
+        manifest
+            .add_dependency("b", Dependency::path("../b"))
+            .unwrap();
+        manifest
+            .add_dev_dependency("c", Version::new(2, 0, 0))
+            .unwrap();
+        manifest.add_build_dependency("d", "0.3.1").unwrap();
+        assert!(manifest.add_dependency("e", "not a version").is_err());
+
+        let toml = to_toml(&manifest).unwrap();
+        assert!(toml.ends_with(
+            "[dependencies]\na = \"1.0.0\"\n\n[dependencies.b]\npath = \"../b\"\n\n\
+             [dev-dependencies]\nc = \"2.0.0\"\n\n[build-dependencies]\nd = \"0.3.1\"\n"
+        ));
+    }
+
+    #[test]
+    fn edition_parses_from_string_and_integer() {
+        let edition = |value: &str| {
+            Manifest::from_toml_str(&format!(
+                "[package]\nname = \"foo\"\nversion = \"0.1.0\"\nedition = {}\n",
+                value
+            ))
+            .map(|manifest| manifest.package.edition)
+        };
+
+        assert_eq!(edition("\"2021\"").unwrap(), Edition::Edition2021);
+        assert_eq!(edition("2021").unwrap(), Edition::Edition2021);
+        assert_eq!(edition("\"2024\"").unwrap(), Edition::Edition2024);
+        assert_eq!(Edition::Edition2024.to_string(), "2024");
+        assert_eq!(edition("2015").unwrap(), Edition::Edition2015);
+        assert!(edition("2019").is_err());
+        assert!(edition("\"2019\"").is_err());
+        assert!(edition("true").is_err());
+    }
+
+    #[test]
+    fn manifest_from_path_reads_cargo_toml() {
+        let path = Path::new("tmp_from_path.toml");
+        fs::write(
+            path,
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\nedition = \"2015\"\n\n\
+             [dependencies]\nbar = \"1.2.3\"\n\n[lints.rust]\nunsafe_code = \"forbid\"\n",
+        )
+        .unwrap();
+        let manifest = Manifest::from_path(path);
+        fs::remove_file(path).unwrap();
+
+        let manifest = manifest.unwrap();
+        assert_eq!(manifest.package.name, "foo");
+        assert_eq!(manifest.package.version, Version::new(0, 1, 0).into());
+        assert_eq!(manifest.package.edition, Edition::Edition2015);
+        assert!(manifest.package.authors.is_empty());
+        assert_eq!(
+            manifest.dependencies.unwrap()["bar"],
+            Version::new(1, 2, 3).into()
+        );
+    }
+
+    #[test]
+    fn publish_setting_serializes_both_forms() {
+        let mut manifest = Manifest::default();
+        assert!(!toml::to_string(&manifest).unwrap().contains("publish"));
+
+        manifest.package.publish = Some(PublishSetting::Bool(false));
+        assert!(toml::to_string(&manifest)
+            .unwrap()
+            .contains("publish = false\n"));
+
+        manifest.package.publish = Some(PublishSetting::Registries(vec!["my-registry".into()]));
+        let toml = toml::to_string(&manifest).unwrap();
+        assert!(toml.contains("publish = [\"my-registry\"]\n"));
+        assert_eq!(
+            Manifest::from_toml_str(&toml).unwrap().package.publish,
+            manifest.package.publish
+        );
+    }
+
+    #[test]
+    fn path_dependencies_round_trip() {
+        let mut manifest = Manifest::default();
+        manifest
+            .dependencies_mut()
+            .insert("bar".into(), Dependency::path("../bar"));
+        manifest
+            .dependencies_mut()
+            .insert("baz".into(), Version::new(1, 0, 0).into());
+
+        let toml = toml::to_string(&manifest).unwrap();
+        assert!(toml.contains("baz = \"1.0.0\"\n"));
+        assert!(toml.contains("[dependencies.bar]\npath = \"../bar\"\n"));
+        assert_eq!(
+            Manifest::from_toml_str(&toml).unwrap().dependencies,
+            manifest.dependencies
+        );
+    }
+
+    #[test]
+    fn dependencies_convert_from_toml_values() {
+        let value = |toml: &str| toml.parse::<toml::Value>().unwrap()["dep"].clone();
+
+        assert_eq!(
+            Dependency::try_from(value("dep = \"1.2.3\"")).unwrap(),
+            Version::new(1, 2, 3).into()
+        );
+        assert_eq!(
+            Dependency::try_from(value(
+                "dep = { version = \"0.4.0\", features = [\"a\", \"b\"], optional = true }"
+            ))
+            .unwrap(),
+            Dependency {
+                version: Some(Version::new(0, 4, 0)),
+                features: vec!["a".to_owned(), "b".to_owned()],
+                ..Dependency::default()
+            }
+        );
+        assert_eq!(
+            Dependency::try_from(value("dep = { git = \"https://example.com/dep\" }")).unwrap(),
+            Dependency {
+                git: Some("https://example.com/dep".to_owned()),
+                ..Dependency::default()
+            }
+        );
+        assert_eq!(
+            Dependency::try_from(value("[dep]\npath = \"../dep\"\nregistry = \"mine\"")).unwrap(),
+            Dependency {
+                path: Some("../dep".to_owned()),
+                registry: Some("mine".to_owned()),
+                ..Dependency::default()
+            }
+        );
+
+        assert!(matches!(
+            Dependency::try_from(value("dep = \"not a version\"")),
+            Err(EphemeralError::Version(_))
+        ));
+        assert!(matches!(
+            Dependency::try_from(value("dep = { features = \"a\" }")),
+            Err(EphemeralError::Deserialize(_))
+        ));
+        assert!(matches!(
+            Dependency::try_from(value("dep = 1")),
+            Err(EphemeralError::InvalidMetadata(_))
+        ));
+    }
+
+    #[test]
+    fn git_dependencies_with_features_round_trip() {
+        let mut manifest = Manifest::default();
+        manifest.dependencies_mut().insert(
+            "dep".into(),
+            Dependency {
+                git: Some("https://example.com/dep".to_owned()),
+                features: vec!["extra".to_owned()],
+                ..Dependency::default()
+            },
+        );
+
+        let toml = toml::to_string(&manifest).unwrap();
+        assert!(toml.contains(
+            "[dependencies.dep]\ngit = \"https://example.com/dep\"\nfeatures = [\"extra\"]\n"
+        ));
+        assert_eq!(
+            Manifest::from_toml_str(&toml).unwrap().dependencies,
+            manifest.dependencies
+        );
+        assert!(manifest.crates_io_dependencies().is_empty());
+    }
+
+    #[test]
+    fn workspace_metadata_and_lints_are_serialized() {
+        let mut release = toml::value::Table::new();
+        release.insert("sign-commit".into(), toml::Value::Boolean(true));
+        let mut metadata = toml::value::Table::new();
+        metadata.insert("release".into(), toml::Value::Table(release));
+
+        let mut lints = Lints::new();
+        lints
+            .entry("rust".into())
+            .or_default()
+            .insert("unsafe_code".into(), LintLevel::Forbid);
+
+        let manifest = WorkspaceManifest {
+            workspace: Workspace {
+                members: vec!["a".into()],
+                metadata: Some(toml::Value::Table(metadata)),
+                lints: Some(lints),
+                ..Workspace::default()
+            },
+        };
+        let toml = to_toml(&manifest).unwrap();
+        assert!(toml.contains("[workspace.metadata.release]\nsign-commit = true\n"));
+        assert!(toml.contains("[workspace.lints.rust]\nunsafe_code = \"forbid\"\n"));
+
+        let parsed: WorkspaceManifest = toml::from_str(&toml).unwrap();
+        assert_eq!(parsed.workspace.metadata, manifest.workspace.metadata);
+        assert_eq!(parsed.workspace.lints, manifest.workspace.lints);
+    }
+
+    #[test]
+    fn manifest_from_toml_str_rejects_unknown_edition() {
+        let toml = "[package]\nname = \"foo\"\nversion = \"0.1.0\"\nedition = \"1999\"\n";
+        assert!(Manifest::from_toml_str(toml).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use crate::error::EphemeralError;
+use crate::rust_tools::Manifest;
+use serde_derive::Deserialize;
+use std::path::PathBuf;
+
+/// Prefix marking file contents in a spec as base64-encoded bytes rather than text.
+pub const BASE64_MARKER: &str = "base64:";
+
+/// Description of a project that can be loaded from a file (TOML with `from_toml_str()`, or any
+/// other format with a serde deserializer) and turned into a builder with
+/// `GenericBuilder::from_spec()`:
+///
+/// ```toml
+/// path = "tmp"
+///
+/// [manifest.package]
+/// name = "foo"
+/// version = "0.1.0"
+///
+/// [[dirs]]
+/// path = "src"
+///
+/// [[dirs.files]]
+/// path = "lib.rs"
+/// contents = "pub fn foo() {}"
+/// ```
+///
+/// Dir paths are relative to the project `path` and file paths to their dir. File contents are
+/// text, unless they start with `base64:`, in which case the rest is decoded as base64 bytes.
+/// If a manifest is given, it is written as the project's `Cargo.toml`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ProjectSpec {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub dirs: Vec<DirSpec>,
+    pub manifest: Option<Manifest>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct DirSpec {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub files: Vec<FileSpec>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct FileSpec {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub contents: String,
+}
+
+impl ProjectSpec {
+    /// Parses a spec from TOML.
+    pub fn from_toml_str(toml: &str) -> Result<ProjectSpec, EphemeralError> {
+        Ok(toml::from_str(toml)?)
+    }
+}
+
+impl FileSpec {
+    /// Returns the contents as bytes, decoding them if they are marked as base64.
+    pub fn bytes(&self) -> Result<Vec<u8>, EphemeralError> {
+        if self.contents.starts_with(BASE64_MARKER) {
+            decode_base64(&self.contents[BASE64_MARKER.len()..])
+        } else {
+            Ok(self.contents.clone().into_bytes())
+        }
+    }
+}
+
+/// Decodes standard base64, ignoring whitespace and padding.
+fn decode_base64(encoded: &str) -> Result<Vec<u8>, EphemeralError> {
+    let invalid = || EphemeralError::InvalidBase64(encoded.to_owned());
+    let mut bytes = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in encoded
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace() && *c != b'=')
+    {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(invalid()),
+        };
+        buffer = (buffer << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    if bits >= 6 {
+        return Err(invalid());
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_contents_are_decoded() {
+        let file = |contents: &str| FileSpec {
+            path: PathBuf::from("file"),
+            contents: contents.to_owned(),
+        };
+
+        assert_eq!(file("base64:AAEC/w==").bytes().unwrap(), vec![0, 1, 2, 255]);
+        assert_eq!(file("base64:aGVsbG8").bytes().unwrap(), b"hello");
+        assert_eq!(file("hello").bytes().unwrap(), b"hello");
+        assert!(file("base64:a$").bytes().is_err());
+        assert!(file("base64:a").bytes().is_err());
+    }
+}
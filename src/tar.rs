@@ -0,0 +1,338 @@
+//! A minimal ustar writer for `Builder::build_tar()`.
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::{archive_name, error::EphemeralError, flatten, merge_dirs, Project};
+
+const BLOCK: usize = 512;
+/// The largest size the 11 octal digits of the size field hold, 8 GiB minus one byte.
+const MAX_SIZE: u64 = 0o77_777_777_777;
+
+/// An entry added to the archive, written by `TarWriter::finish()`.
+struct Entry {
+    name: String,
+    kind: u8,
+    mode: u32,
+    contents: Vec<u8>,
+}
+
+/// Writes the dirs and files of projects as entries of a tar archive, with paths relative to one
+/// root. Parent dirs that weren't added explicitly get entries too, each dir only once. Entries
+/// are checked as they are added and kept until `finish()`, so an entry the format can't hold
+/// fails the build before anything is written.
+pub(crate) struct TarWriter<'a, W: Write> {
+    root: &'a Path,
+    writer: W,
+    dirs: BTreeSet<String>,
+    entries: Vec<Entry>,
+}
+
+impl<'a, W: Write> TarWriter<'a, W> {
+    pub(crate) fn new(root: &'a Path, writer: W) -> Self {
+        TarWriter {
+            root,
+            writer,
+            dirs: BTreeSet::new(),
+            entries: vec![],
+        }
+    }
+
+    /// Adds every dir and file of `project`, with file contents passed through its
+    /// `map_contents` transform. Dirs added more than once are subject to its `duplicate_dirs`
+    /// policy, and then merged so that each path gets one entry: extracting gives what
+    /// `DuplicateDirs::Append` creates.
+    pub(crate) fn add_project(&mut self, project: &Project) -> Result<(), EphemeralError> {
+        let dirs = merge_dirs(&project.resolved_dirs()?);
+        for dir in flatten(&dirs) {
+            let name = archive_name(self.root, &dir.path)?;
+            self.add_dirs(&name, dir.readonly)?;
+            for file in dir.files.iter() {
+                let name = archive_name(self.root, &file.path)?;
+                if let Some((parent, _)) = name.rsplit_once('/') {
+                    self.add_dirs(parent, false)?;
+                }
+                let contents = file.contents_with(project.map.as_ref())?;
+                let mode = file.mode.or(project.default_mode).unwrap_or(0o644);
+                let mode = if file.readonly { mode & !0o222 } else { mode };
+                self.entry(name, b'0', mode, contents.into_owned())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes every entry and then the two empty blocks that end the archive.
+    pub(crate) fn finish(mut self) -> Result<(), EphemeralError> {
+        for entry in self.entries.iter() {
+            let mut header = [0u8; BLOCK];
+            let (prefix, name) = split_name(&entry.name)?;
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            octal(&mut header[100..108], u64::from(entry.mode));
+            octal(&mut header[108..116], 0);
+            octal(&mut header[116..124], 0);
+            octal(&mut header[124..136], entry.contents.len() as u64);
+            octal(&mut header[136..148], 0);
+            header[156] = entry.kind;
+            header[257..263].copy_from_slice(b"ustar\0");
+            header[263..265].copy_from_slice(b"00");
+            header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+            header[148..156].copy_from_slice(b"        ");
+            let checksum: u64 = header.iter().map(|&byte| u64::from(byte)).sum();
+            octal(&mut header[148..155], checksum);
+
+            self.writer.write_all(&header)?;
+            self.writer.write_all(&entry.contents)?;
+            let padding = (BLOCK - entry.contents.len() % BLOCK) % BLOCK;
+            self.writer.write_all(&[0; BLOCK][..padding])?;
+        }
+        self.writer.write_all(&[0; 2 * BLOCK])?;
+
+        Ok(self.writer.flush()?)
+    }
+
+    /// Adds entries for `name` and its parents that don't have one yet. Only `name` itself is
+    /// marked read-only.
+    fn add_dirs(&mut self, name: &str, readonly: bool) -> Result<(), EphemeralError> {
+        let mut end = 0;
+        while end < name.len() {
+            end = name[end..].find('/').map_or(name.len(), |i| end + i);
+            let dir = &name[..end];
+            if self.dirs.insert(dir.to_owned()) {
+                let mode = if readonly && end == name.len() {
+                    0o555
+                } else {
+                    0o755
+                };
+                self.entry(format!("{}/", dir), b'5', mode, vec![])?;
+            }
+            end += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Adds an entry, checking its name and size right away so the error points at the path
+    /// that caused it.
+    fn entry(&mut self, name: String, kind: u8, mode: u32, contents: Vec<u8>) -> io::Result<()> {
+        split_name(&name)?;
+        check_size(&name, contents.len() as u64)?;
+        self.entries.push(Entry {
+            name,
+            kind,
+            mode,
+            contents,
+        });
+
+        Ok(())
+    }
+}
+
+/// Splits `name` into the ustar prefix and name fields of at most 155 and 100 bytes.
+fn split_name(name: &str) -> io::Result<(&str, &str)> {
+    if name.len() <= 100 {
+        return Ok(("", name));
+    }
+    name.match_indices('/')
+        .map(|(i, _)| (&name[..i], &name[i + 1..]))
+        .find(|(prefix, rest)| prefix.len() <= 155 && rest.len() <= 100 && !rest.is_empty())
+        .ok_or_else(|| io::Error::other(format!("{} is too long for a tar entry", name)))
+}
+
+/// Sizes of 8 GiB and more need the base-256 extension of the size field, which isn't written.
+fn check_size(name: &str, size: u64) -> io::Result<()> {
+    if size > MAX_SIZE {
+        let message = format!("{} is too large for a tar entry, at most 8 GiB", name);
+        return Err(io::Error::other(message));
+    }
+
+    Ok(())
+}
+
+/// Writes `value` as zero-padded octal digits followed by a NUL, filling `field`. `value` has to
+/// fit, which `TarWriter::entry()` checks for sizes.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{Builder, GenericBuilder, RustBuilder};
+    use crate::rust_tools::Manifest;
+    use crate::{Dir, DuplicateDirs};
+
+    /// Reads `(name, kind, mode, contents)` for every entry of a tar archive.
+    fn entries(tar: &[u8]) -> Vec<(String, u8, u32, Vec<u8>)> {
+        let field = |header: &[u8], range: std::ops::Range<usize>| {
+            let bytes = &header[range];
+            let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            String::from_utf8(bytes[..end].to_vec()).unwrap()
+        };
+        let mut entries = vec![];
+        let mut pos = 0;
+        while tar[pos..pos + BLOCK].iter().any(|&b| b != 0) {
+            let header = &tar[pos..pos + BLOCK];
+            let sum: u64 = header
+                .iter()
+                .enumerate()
+                .map(|(i, &b)| {
+                    if (148..156).contains(&i) {
+                        32
+                    } else {
+                        u64::from(b)
+                    }
+                })
+                .sum();
+            assert_eq!(
+                u64::from_str_radix(&field(header, 148..155), 8).unwrap(),
+                sum
+            );
+            let prefix = field(header, 345..500);
+            let name = field(header, 0..100);
+            let name = if prefix.is_empty() {
+                name
+            } else {
+                format!("{}/{}", prefix, name)
+            };
+            let mode = u32::from_str_radix(&field(header, 100..107), 8).unwrap();
+            let size = usize::from_str_radix(&field(header, 124..135), 8).unwrap();
+            let contents = tar[pos + BLOCK..pos + BLOCK + size].to_vec();
+            entries.push((name, header[156], mode, contents));
+            pos += BLOCK + size.div_ceil(BLOCK) * BLOCK;
+        }
+        assert_eq!(tar.len(), pos + 2 * BLOCK);
+
+        entries
+    }
+
+    #[test]
+    fn build_tar_writes_dirs_and_files_without_touching_disk() {
+        let long = "d".repeat(90);
+        let mut tar = vec![];
+        RustBuilder::new("tmp_tar")
+            .add_cargo_toml(Manifest::try_from("archived", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .add_dir(Dir::new("tmp_tar/src/bin").add_file("main.rs", b"fn main() {}"))
+            .add_dir(
+                Dir::new("tmp_tar/ro")
+                    .add_readonly_file("frozen", b"x")
+                    .readonly(),
+            )
+            .add_dir(Dir::new(format!("tmp_tar/{}", long)).add_file(&long, b"long"))
+            .add_dir(Dir::new("tmp_tar/bin").add_file_with_mode("run", b"", 0o755))
+            .map_contents(|_, bytes| bytes.to_ascii_uppercase())
+            .build_tar(&mut tar)
+            .unwrap();
+        assert!(!Path::new("tmp_tar").exists());
+
+        let entries = entries(&tar);
+        let names: Vec<_> = entries.iter().map(|entry| entry.0.as_str()).collect();
+        let long_file = format!("{}/{}", long, long);
+        assert_eq!(
+            names,
+            [
+                "Cargo.toml",
+                "src/",
+                "src/bin/",
+                "src/bin/main.rs",
+                "ro/",
+                "ro/frozen",
+                &format!("{}/", long),
+                &long_file,
+                "bin/",
+                "bin/run",
+            ]
+        );
+        let entry = |name: &str| entries.iter().find(|entry| entry.0 == name).unwrap();
+        assert_eq!(entry("src/").1, b'5');
+        assert_eq!(entry("src/").2, 0o755);
+        assert_eq!(
+            entry("src/bin/main.rs"),
+            &(
+                "src/bin/main.rs".into(),
+                b'0',
+                0o644,
+                b"FN MAIN() {}".to_vec()
+            )
+        );
+        assert_eq!(entry("ro/").2, 0o555);
+        assert_eq!(entry("ro/frozen").2, 0o444);
+        assert!(String::from_utf8_lossy(&entry("Cargo.toml").3).contains("NAME = \"ARCHIVED\""));
+        assert_eq!(entry(&long_file).3, b"LONG");
+        assert_eq!(entry("bin/run").2, 0o755);
+    }
+
+    #[test]
+    fn build_tar_with_clear_on_drop_leaves_the_dir_on_disk_alone() {
+        let project = GenericBuilder::new("tmp_tar_on_disk").build().unwrap();
+        GenericBuilder::new("tmp_tar_on_disk")
+            .clear_on_drop(true)
+            .build_tar(io::sink())
+            .unwrap();
+
+        assert!(project.path.exists());
+        project.clear();
+    }
+
+    #[test]
+    fn build_tar_follows_the_duplicate_dirs_policy() {
+        let builder = || {
+            GenericBuilder::new("tmp_tar_dup")
+                .add_dir(Dir::new("tmp_tar_dup/d").add_file("shared", b"first"))
+                .add_dir(Dir::new("tmp_tar_dup/d").add_file("shared", b"second"))
+        };
+
+        let mut tar = vec![];
+        builder().build_tar(&mut tar).unwrap();
+        let entries = entries(&tar);
+        let names: Vec<_> = entries.iter().map(|entry| entry.0.as_str()).collect();
+        assert_eq!(names, ["d/", "d/shared"]);
+        assert_eq!(entries[1].3, b"second");
+
+        let mut tar = vec![];
+        let result = builder()
+            .duplicate_dirs(DuplicateDirs::Error)
+            .build_tar(&mut tar);
+        match result {
+            Err(EphemeralError::DuplicateFile(path)) => {
+                assert_eq!(path, Path::new("tmp_tar_dup/d/shared"))
+            }
+            other => panic!("expected DuplicateFile, got {:?}", other),
+        }
+        assert!(tar.is_empty());
+        assert!(!Path::new("tmp_tar_dup").exists());
+    }
+
+    #[test]
+    fn build_tar_checks_entries_before_writing() {
+        let long = "n".repeat(101);
+        let mut tar = vec![];
+        let result = GenericBuilder::new("tmp_tar_limits")
+            .add_dir(
+                Dir::new("tmp_tar_limits")
+                    .add_file("a", b"")
+                    .add_file(&long, b""),
+            )
+            .build_tar(&mut tar);
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("is too long for a tar entry"), "{}", err);
+        assert!(tar.is_empty());
+
+        assert!(check_size("big", MAX_SIZE).is_ok());
+        let err = check_size("big", MAX_SIZE + 1).unwrap_err();
+        assert!(err.to_string().contains("big is too large"), "{}", err);
+        assert!(!Path::new("tmp_tar_limits").exists());
+    }
+
+    #[test]
+    fn build_tar_rejects_paths_outside_the_root() {
+        let result = GenericBuilder::new("tmp_tar_outside")
+            .add_dir(Dir::new("elsewhere").add_file("a", b""))
+            .build_tar(io::sink());
+        assert!(matches!(result, Err(EphemeralError::OutsideRoot(_))));
+    }
+}
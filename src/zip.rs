@@ -0,0 +1,362 @@
+//! A minimal zip writer for `Builder::build_zip()`. Entries are stored without compression, so
+//! no deflate implementation is needed.
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::{archive_name, error::EphemeralError, flatten, merge_dirs, Project};
+
+/// Flag marking entry names as UTF-8.
+const UTF8_NAMES: u16 = 1 << 11;
+/// Version 2.0 of the format, the first with dirs, made on Unix so the modes are read.
+const VERSION_NEEDED: u16 = 20;
+const VERSION_MADE_BY: u16 = 3 << 8 | VERSION_NEEDED;
+/// 1980-01-01, the earliest DOS date, so archives don't depend on the clock.
+const DOS_DATE: u16 = 1 << 5 | 1;
+
+/// An entry added to the archive, written by `ZipWriter::finish()`.
+struct Entry {
+    name: String,
+    mode: u32,
+    contents: Vec<u8>,
+}
+
+/// The fields of an entry's headers, worked out before anything is written.
+struct Layout {
+    crc: u32,
+    size: u32,
+    name_len: u16,
+    offset: u32,
+}
+
+/// Writes the dirs and files of projects as entries of a zip archive, with paths relative to one
+/// root. Parent dirs that weren't added explicitly get entries too, each dir only once. Entries
+/// are kept until `finish()`, which checks that the archive can hold all of them before writing
+/// any, so a failed build doesn't leave half an archive behind.
+pub(crate) struct ZipWriter<'a, W: Write> {
+    root: &'a Path,
+    writer: W,
+    dirs: BTreeSet<String>,
+    entries: Vec<Entry>,
+}
+
+impl<'a, W: Write> ZipWriter<'a, W> {
+    pub(crate) fn new(root: &'a Path, writer: W) -> Self {
+        ZipWriter {
+            root,
+            writer,
+            dirs: BTreeSet::new(),
+            entries: vec![],
+        }
+    }
+
+    /// Adds every dir and file of `project`, with file contents passed through its
+    /// `map_contents` transform. Dirs added more than once are subject to its `duplicate_dirs`
+    /// policy, and then merged so that each path gets one entry: extracting gives what
+    /// `DuplicateDirs::Append` creates.
+    pub(crate) fn add_project(&mut self, project: &Project) -> Result<(), EphemeralError> {
+        let dirs = merge_dirs(&project.resolved_dirs()?);
+        for dir in flatten(&dirs) {
+            let name = archive_name(self.root, &dir.path)?;
+            self.add_dirs(&name, dir.readonly)?;
+            for file in dir.files.iter() {
+                let name = archive_name(self.root, &file.path)?;
+                if let Some((parent, _)) = name.rsplit_once('/') {
+                    self.add_dirs(parent, false)?;
+                }
+                let contents = file.contents_with(project.map.as_ref())?;
+                let mode = file.mode.or(project.default_mode).unwrap_or(0o644);
+                let mode = if file.readonly { mode & !0o222 } else { mode };
+                self.entry(name, 0o100_000 | mode, contents.into_owned())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes every entry and then the central directory that ends the archive. Fails without
+    /// writing anything if there are more entries, or the archive is larger, than zip can hold
+    /// without zip64.
+    pub(crate) fn finish(mut self) -> Result<(), EphemeralError> {
+        let count = u16::try_from(self.entries.len())
+            .map_err(|_| too_large(&format!("an archive of {} entries", self.entries.len())))?;
+        let mut layouts = vec![];
+        let mut start = 0u64;
+        for entry in self.entries.iter() {
+            let layout = Layout {
+                crc: crc32(&entry.contents),
+                size: u32::try_from(entry.contents.len()).map_err(|_| too_large(&entry.name))?,
+                name_len: name_len(&entry.name)?,
+                offset: offset(start)?,
+            };
+            start += (30 + entry.name.len() + entry.contents.len()) as u64;
+            layouts.push(layout);
+        }
+        let central_len: u64 = self
+            .entries
+            .iter()
+            .map(|entry| (46 + entry.name.len()) as u64)
+            .sum();
+        let central_start = offset(start)?;
+        let central_len = offset(central_len)?;
+
+        let entries = &self.entries;
+        for (entry, layout) in entries.iter().zip(layouts.iter()) {
+            let mut header = vec![];
+            header.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+            header.extend_from_slice(&entry_fields(layout));
+            header.extend_from_slice(entry.name.as_bytes());
+            self.writer.write_all(&header)?;
+            self.writer.write_all(&entry.contents)?;
+        }
+        for (entry, layout) in entries.iter().zip(layouts.iter()) {
+            let mut header = vec![];
+            header.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+            header.extend_from_slice(&VERSION_MADE_BY.to_le_bytes());
+            header.extend_from_slice(&entry_fields(layout));
+            // No comment, on the first disk, no internal attributes.
+            header.extend_from_slice(&[0; 6]);
+            let dos_dir = if entry.name.ends_with('/') { 0x10 } else { 0 };
+            header.extend_from_slice(&(entry.mode << 16 | dos_dir).to_le_bytes());
+            header.extend_from_slice(&layout.offset.to_le_bytes());
+            header.extend_from_slice(entry.name.as_bytes());
+            self.writer.write_all(&header)?;
+        }
+
+        let mut end = vec![];
+        end.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        end.extend_from_slice(&[0; 4]);
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&central_len.to_le_bytes());
+        end.extend_from_slice(&central_start.to_le_bytes());
+        end.extend_from_slice(&[0; 2]);
+        self.writer.write_all(&end)?;
+
+        Ok(self.writer.flush()?)
+    }
+
+    /// Adds entries for `name` and its parents that don't have one yet. Only `name` itself is
+    /// marked read-only.
+    fn add_dirs(&mut self, name: &str, readonly: bool) -> Result<(), EphemeralError> {
+        let mut end = 0;
+        while end < name.len() {
+            end = name[end..].find('/').map_or(name.len(), |i| end + i);
+            let dir = &name[..end];
+            if self.dirs.insert(dir.to_owned()) {
+                let mode = if readonly && end == name.len() {
+                    0o555
+                } else {
+                    0o755
+                };
+                self.entry(format!("{}/", dir), 0o040_000 | mode, vec![])?;
+            }
+            end += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Adds an entry, checking its name right away so the error points at the path that caused
+    /// it.
+    fn entry(&mut self, name: String, mode: u32, contents: Vec<u8>) -> Result<(), EphemeralError> {
+        name_len(&name)?;
+        self.entries.push(Entry {
+            name,
+            mode,
+            contents,
+        });
+
+        Ok(())
+    }
+}
+
+/// The fields local and central headers share, from the version needed to the extra field
+/// length.
+fn entry_fields(layout: &Layout) -> Vec<u8> {
+    let mut fields = vec![];
+    fields.extend_from_slice(&VERSION_NEEDED.to_le_bytes());
+    fields.extend_from_slice(&UTF8_NAMES.to_le_bytes());
+    fields.extend_from_slice(&0u16.to_le_bytes());
+    fields.extend_from_slice(&0u16.to_le_bytes());
+    fields.extend_from_slice(&DOS_DATE.to_le_bytes());
+    fields.extend_from_slice(&layout.crc.to_le_bytes());
+    fields.extend_from_slice(&layout.size.to_le_bytes());
+    fields.extend_from_slice(&layout.size.to_le_bytes());
+    fields.extend_from_slice(&layout.name_len.to_le_bytes());
+    fields.extend_from_slice(&0u16.to_le_bytes());
+    fields
+}
+
+/// Offsets past 4 GiB need the zip64 extensions, which this writer doesn't support.
+fn offset(offset: u64) -> Result<u32, EphemeralError> {
+    u32::try_from(offset).map_err(|_| too_large("the archive"))
+}
+
+/// Entry names are at most 65535 bytes long, with or without zip64.
+fn name_len(name: &str) -> Result<u16, EphemeralError> {
+    u16::try_from(name.len()).map_err(|_| {
+        let start: String = name.chars().take(40).collect();
+        let message = format!(
+            "the {} byte path {}... is too long for a zip entry name",
+            name.len(),
+            start
+        );
+        io::Error::other(message).into()
+    })
+}
+
+fn too_large(what: &str) -> EphemeralError {
+    let message = format!("{} is too large for a zip archive without zip64", what);
+    io::Error::other(message).into()
+}
+
+/// The CRC-32 (IEEE) checksum zip stores for each entry.
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            if crc & 1 == 1 {
+                crc >> 1 ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{Builder, GenericBuilder, RustBuilder};
+    use crate::rust_tools::Manifest;
+    use crate::Dir;
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    }
+
+    /// Reads `(name, mode, contents)` for every entry of a zip archive through its central
+    /// directory, checking each against its local header.
+    fn entries(zip: &[u8]) -> Vec<(String, u32, Vec<u8>)> {
+        let end = zip.len() - 22;
+        assert_eq!(u32_at(zip, end), 0x0605_4b50);
+        let count = u16_at(zip, end + 10) as usize;
+        let mut pos = u32_at(zip, end + 16) as usize;
+        let mut entries = vec![];
+        for _ in 0..count {
+            assert_eq!(u32_at(zip, pos), 0x0201_4b50);
+            let crc = u32_at(zip, pos + 16);
+            let size = u32_at(zip, pos + 24) as usize;
+            let name_len = u16_at(zip, pos + 28) as usize;
+            let mode = u32_at(zip, pos + 38) >> 16;
+            let local = u32_at(zip, pos + 42) as usize;
+            let name = String::from_utf8(zip[pos + 46..pos + 46 + name_len].to_vec()).unwrap();
+
+            assert_eq!(u32_at(zip, local), 0x0403_4b50);
+            assert_eq!(&zip[local + 30..local + 30 + name_len], name.as_bytes());
+            let start = local + 30 + name_len;
+            let contents = zip[start..start + size].to_vec();
+            assert_eq!(crc32(&contents), crc);
+            entries.push((name, mode, contents));
+            pos += 46 + name_len;
+        }
+        assert_eq!(pos, end);
+
+        entries
+    }
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn build_zip_writes_dirs_and_files_without_touching_disk() {
+        let mut zip = vec![];
+        RustBuilder::new("tmp_zip")
+            .add_cargo_toml(Manifest::try_from("zipped", "0.1.0", &[], None, None).unwrap())
+            .unwrap()
+            .add_dir(Dir::new("tmp_zip/src/bin").add_file("main.rs", b"fn main() {}"))
+            .add_dir(
+                Dir::new("tmp_zip/ro")
+                    .add_readonly_file("frozen", b"x")
+                    .readonly(),
+            )
+            .add_dir(Dir::new("tmp_zip/bin").add_file_with_mode("run", b"#!/bin/sh\n", 0o755))
+            .build_zip(&mut zip)
+            .unwrap();
+        assert!(!Path::new("tmp_zip").exists());
+
+        let entries = entries(&zip);
+        let names: Vec<_> = entries.iter().map(|entry| entry.0.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "Cargo.toml",
+                "src/",
+                "src/bin/",
+                "src/bin/main.rs",
+                "ro/",
+                "ro/frozen",
+                "bin/",
+                "bin/run",
+            ]
+        );
+        let entry = |name: &str| entries.iter().find(|entry| entry.0 == name).unwrap();
+        assert_eq!(entry("src/").1, 0o040_755);
+        assert_eq!(
+            entry("src/bin/main.rs"),
+            &(
+                "src/bin/main.rs".into(),
+                0o100_644,
+                b"fn main() {}".to_vec()
+            )
+        );
+        assert_eq!(entry("ro/").1, 0o040_555);
+        assert_eq!(entry("ro/frozen").1, 0o100_444);
+        assert_eq!(entry("bin/run").1, 0o100_755);
+        assert!(String::from_utf8_lossy(&entry("Cargo.toml").2).contains("name = \"zipped\""));
+    }
+
+    #[test]
+    fn build_zip_checks_its_limits_before_writing() {
+        let long = "n".repeat(usize::from(u16::MAX) + 1);
+        let mut zip = vec![];
+        let result = GenericBuilder::new("tmp_zip_limits")
+            .add_dir(
+                Dir::new("tmp_zip_limits")
+                    .add_file("a", b"")
+                    .add_file(&long, b""),
+            )
+            .build_zip(&mut zip);
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("the 65536 byte path nnn"), "{}", err);
+        assert!(zip.is_empty());
+
+        let files = (0..=u16::MAX).fold(Dir::new("tmp_zip_limits"), |dir, i| {
+            dir.add_file(i.to_string(), b"")
+        });
+        let result = GenericBuilder::new("tmp_zip_limits")
+            .add_dir(files)
+            .build_zip(&mut zip);
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("an archive of 65536 entries"), "{}", err);
+        assert!(zip.is_empty());
+        assert!(!Path::new("tmp_zip_limits").exists());
+    }
+
+    #[test]
+    fn build_zip_rejects_paths_outside_the_root() {
+        let result = GenericBuilder::new("tmp_zip_outside")
+            .add_dir(Dir::new("elsewhere").add_file("a", b""))
+            .build_zip(io::sink());
+        assert!(matches!(result, Err(EphemeralError::OutsideRoot(_))));
+    }
+}